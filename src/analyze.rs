@@ -1,83 +1,259 @@
+use std::collections::HashMap;
 use std::io::Read;
-use std::{cmp::Ordering, collections::BinaryHeap};
 
-use rayon::iter::{FromParallelIterator, IntoParallelRefIterator};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator};
 use rayon::{iter::ParallelIterator, str::ParallelString};
 
+use super::effort;
+use super::event;
+use super::input;
+use super::keylog;
 use super::layout;
+use super::metric::{self, Metric};
+use super::model;
+
+// Rows of a press, relative to the home of the finger that made it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Row {
+  Number,
+  Top,
+  Home,
+  Bottom,
+  Thumb, // Any press by a thumb
+}
+
+pub static ROWS: [Row; 5] = [Row::Number, Row::Top, Row::Home, Row::Bottom, Row::Thumb];
+
+// What held up a press, whichever constraint its start waited on last
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Cause {
+  PressGap, // The last press ending, plus the gap between presses
+  SameHand, // The hand's last press ending before it could move
+  Travel,   // A finger still busy from before, and then its move
+  Modifier, // A modifier's move, longer than the main key's
+}
+
+pub static CAUSES: [Cause; 4] = [Cause::PressGap, Cause::SameHand, Cause::Travel, Cause::Modifier];
+
+// The time between the starts of presses, put down to the cause
+// that set each start and the finger behind it
+#[derive(Default, Clone)]
+pub struct CriticalPath {
+  pub time: [i32; 4],   // ms, indexed by Cause
+  pub counts: [u32; 4], // # of presses
+  pub finger_time: Vec<[i32; 4]>,
+}
+
+impl CriticalPath {
+  fn count(&mut self, cause: Cause, finger: usize, time: i32) {
+    self.time[cause as usize] += time;
+    self.counts[cause as usize] += 1;
+    self.count_time(finger, cause as usize, time);
+  }
+
+  pub fn add(&mut self, other: &CriticalPath) {
+    for c in 0..CAUSES.len() {
+      self.time[c] += other.time[c];
+      self.counts[c] += other.counts[c];
+    }
+    for (i, times) in other.finger_time.iter().enumerate() {
+      for (c, t) in times.iter().enumerate() {
+        self.count_time(i, c, *t);
+      }
+    }
+  }
+
+  fn count_time(&mut self, finger: usize, cause: usize, time: i32) {
+    if self.finger_time.len() <= finger {
+      self.finger_time.resize(finger + 1, [0; 4]);
+    }
+    self.finger_time[finger][cause] += time;
+  }
+
+  pub fn percent(&self, cause: Cause) -> f32 {
+    let total: i32 = self.time.iter().sum();
+    if total == 0 {
+      return 0.0;
+    }
+    (self.time[cause as usize] as f32) * 100.0 / (total as f32)
+  }
+
+  // The finger most of cause's time is down to
+  pub fn worst_finger(&self, cause: Cause) -> Option<usize> {
+    (0..self.finger_time.len())
+      .filter(|i| self.finger_time[*i][cause as usize] > 0)
+      .max_by_key(|i| self.finger_time[*i][cause as usize])
+  }
+}
+
+// Keys this close sideways (in u) are in the same column
+static STRETCH_EPSILON: f32 = 0.01;
+
+fn percent(n: u32, total: u32) -> f32 {
+  if total == 0 {
+    return 0.0;
+  }
+  (n as f32) * 100.0 / (total as f32)
+}
 
 #[derive(Default)]
 pub struct Timeline {
   pub fingers: Vec<Vec<Keyframe>>,
-  pub finger_counts: [u32; 10], // number of presses
+  pub finger_info: Vec<layout::Finger>,
+  pub finger_counts: Vec<u32>, // number of presses
+  pub finger_errors: Vec<u32>, // number of presses meant for each finger that were mistyped
+  pub finger_dist: Vec<f32>, // in u
+  pub finger_move_time: Vec<i32>, // ms spent moving
+  pub finger_press_time: Vec<i32>, // ms spent pressing
+  pub finger_reach: Vec<f32>, // in u, farthest key from home pressed
   pub total_time: i32,
   pub total_dist: f32, // in u
   pub total_words: u32,
-  pub total_chars: u32,
+  pub total_chars: u32, // # of chars the layout could type
+  pub total_unmapped: u32, // # of chars skipped because the layout can't type them
+  pub total_strokes: u32, // # of key combos pressed
   pub total_switches: u32, // # of times alternated between L & R
+  pub row_counts: [u32; 5], // # of presses on each Row
+  pub stretch_presses: u32, // # of presses off a finger's own column
+  pub total_swipe: f32, // in u, gesture typing path of every word, only in mobile mode
+  pub swipe_words: u32, // # of words in total_swipe
+  pub key_pitch: f32, // mm between keys
+  pub effort: effort::Effort, // Only with an effort model
+  pub critical: CriticalPath, // What the presses waited on, not for recorded sessions
+  pub metrics: Vec<(String, f32)>, // Values of the built in metrics, then the model's
 }
 
 impl Timeline {
+  // The value of the metric called name, 0 if it wasn't measured
+  pub fn metric(&self, name: &str) -> f32 {
+    self.metrics.iter().find(|(n, _)| n == name).map_or(0.0, |(_, v)| *v)
+  }
+
   pub fn wpm(&self) -> u16 {
     (60000.0 * (self.total_words as f32) / (self.total_time as f32)) as u16
   }
 
+  // Presses of every key, including modifiers held in a combo
+  pub fn total_presses(&self) -> u32 {
+    self.finger_counts.iter().sum()
+  }
+
   pub fn usage_percent(&self, i: usize) -> u32 {
-    if i >= 10 {
+    if i >= self.finger_counts.len() {
+      return 0;
+    }
+    (self.finger_counts[i] * 100) / self.total_presses()
+  }
+
+  pub fn total_errors(&self) -> u32 {
+    self.finger_errors.iter().sum()
+  }
+
+  pub fn error_percent(&self, i: usize) -> f32 {
+    if i >= self.finger_counts.len() || self.finger_counts[i] == 0 {
+      return 0.0;
+    }
+    (self.finger_errors[i] as f32) * 100.0 / (self.finger_counts[i] as f32)
+  }
+
+  // Percent of presses made with a thumb
+  pub fn thumb_percent(&self) -> u32 {
+    if self.total_presses() == 0 {
       return 0;
     }
-    (self.finger_counts[i] * 100) / (self.total_chars)
+    let thumbs: u32 = (0..self.finger_counts.len())
+      .filter(|i| self.finger_info.get(*i).is_some_and(|f| f.is_thumb()))
+      .map(|i| self.finger_counts[i])
+      .sum();
+    (thumbs * 100) / self.total_presses()
+  }
+
+  pub fn row_percent(&self, row: Row) -> f32 {
+    percent(self.row_counts[row as usize], self.total_presses())
+  }
+
+  pub fn home_row_percent(&self) -> f32 {
+    self.row_percent(Row::Home)
+  }
+
+  // Share of the presses made by hand, leaving out thumbs like
+  // alternation does
+  pub fn hand_percent(&self, hand: layout::Hand) -> f32 {
+    let count = |hand: Option<layout::Hand>| -> u32 {
+      (0..self.finger_counts.len())
+        .filter(|i| {
+          self.finger_info.get(*i).is_some_and(|f| !f.is_thumb() && hand.is_none_or(|h| f.hand == h))
+        })
+        .map(|i| self.finger_counts[i])
+        .sum()
+    };
+    percent(count(Some(hand)), count(None))
+  }
+
+  pub fn stretch_percent(&self) -> f32 {
+    percent(self.stretch_presses, self.total_presses())
+  }
+
+  pub fn strokes_per_char(&self) -> f32 {
+    self.total_strokes as f32 / (self.total_chars as f32)
+  }
+
+  // Chorded layouts type whole words in one stroke
+  pub fn strokes_per_word(&self) -> f32 {
+    if self.total_words == 0 {
+      return 0.0;
+    }
+    self.total_strokes as f32 / (self.total_words as f32)
   }
 
   pub fn u_per_char(&self) -> f32 {
+    if self.total_chars == 0 {
+      return 0.0;
+    }
     self.total_dist / (self.total_chars as f32)
   }
 
   pub fn alternating_percent(&self) -> u32 {
+    if self.total_chars < 2 {
+      return 0;
+    }
     (self.total_switches * 100) / (self.total_chars - 1)
   }
 
-  pub fn total_dist_mm(&self) -> f32 {
-    self.total_dist * 19.05
+  pub fn coverage_percent(&self) -> f32 {
+    let total = self.total_chars + self.total_unmapped;
+    if total == 0 {
+      return 100.0;
+    }
+    (self.total_chars as f32) * 100.0 / (total as f32)
   }
 
-  pub fn total_dist_m(&self) -> f32 {
-    (self.total_dist / 1000.0) * 19.05
+  // Average length of the path swiped to gesture type a word, in u
+  pub fn swipe_per_word(&self) -> f32 {
+    self.total_swipe / (self.swipe_words as f32)
   }
 
-  pub fn total_dist_km(&self) -> f32 {
-    (self.total_dist / 1000000.0) * 19.05
+  pub fn total_dist_mm(&self) -> f32 {
+    self.total_dist * self.key_pitch
   }
-}
 
-// TODO: this is a bad equality
-impl Eq for Timeline {}
-impl PartialEq for Timeline {
-  fn eq(&self, other: &Self) -> bool {
-    self.total_dist == other.total_dist
+  pub fn total_dist_m(&self) -> f32 {
+    (self.total_dist / 1000.0) * self.key_pitch
   }
-}
 
-impl Ord for Timeline {
-  fn cmp(&self, other: &Self) -> Ordering {
-    // For this I don't care about floating point inaccuracy
-    let this_val = &self.total_dist;
-    let other_val = &other.total_dist;
-
-    if this_val == other_val {
-      Ordering::Equal
-    } else if this_val < other_val {
-      Ordering::Less
-    } else {
-      Ordering::Greater
-    }
+  pub fn total_dist_km(&self) -> f32 {
+    (self.total_dist / 1000000.0) * self.key_pitch
   }
 }
 
-impl PartialOrd for Timeline {
-  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-    Some(self.cmp(other))
-  }
+// What typing one stroke of a text cost, see stroke_costs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrokeCost {
+  pub idx: usize,       // Byte offset in the text
+  pub chars: u32,       // # of chars it types
+  pub time: i32,        // ms from the start of the previous press to the start of its last one
+  pub dist: f32,        // u moved to type it, including fingers going home out of its way
+  pub same_finger: u32, // # of same finger bigrams it ends
 }
 
 #[derive(Default, Clone, Copy)]
@@ -90,61 +266,326 @@ pub struct Keyframe {
   on_char: char,
 }
 
-static PRESS_DUR: i32 = 50;
-static PRESS_GAP: i32 = 25; // ms delay between presses
-static MOVE_SPEED: f32 = 150.0; // Movement speed in ms / u
+pub static KEY_PITCH: f32 = 19.05; // mm between keys on a keyboard
+// Defaults of model::Timing
+pub static PRESS_DUR: i32 = 50;
+pub static PRESS_GAP: i32 = 25; // ms delay between presses
+pub static MOVE_SPEED: f32 = 150.0; // Movement speed in ms / u
 static PARALLEL_SIZE: usize = 90000;
 
-pub fn gen_timeline<'a>(string: &str, gen_anim: bool, lay: &'a layout::Layout) -> Timeline {
-  let mut fingers: Vec<Vec<Keyframe>> = vec![Default::default(); lay.homes.len()];
+pub fn gen_timeline_model(string: &str, gen_anim: bool, lay: &layout::Layout, model: &model::Model) -> Timeline {
+  let mut sim = Sim::new(gen_anim, lay, model);
+  simulate(&mut sim, string, lay, model);
 
-  let mut finger_usage_cnt = [0; 10];
+  let mut tl = sim.finish(string);
+  if let Some(cfg) = &model.effort {
+    tl.effort = effort::effort(string, lay, cfg);
+  }
+  if model.mobile.is_some() {
+    for len in string.split_whitespace().filter_map(|word| swipe_len(word, lay)) {
+      tl.total_swipe += len;
+      tl.swipe_words += 1;
+    }
+  }
+  tl
+}
 
-  for i in 0..lay.homes.len() {
-    fingers[i].push(Keyframe {
-      pos: layout::Pos {
-        x: lay.homes[i].pos.x,
-        y: lay.homes[i].pos.y,
-      },
-      time: 0,
-      start_press: false,
-      on_char: lay.homes[i].pressed,
-    });
+// Timeline of a recorded session, measured like a simulated one.
+// Modifiers count as part of the combo of the first key pressed while
+// they're held, or as a combo of their own if there isn't one. Every
+// other key counts as a char, including backspace
+pub fn gen_timeline_log(presses: &[keylog::KeyPress], gen_anim: bool, lay: &layout::Layout, model: &model::Model) -> Timeline {
+  let mut sim = Sim::new(gen_anim, lay, model);
+  let mut mods: Vec<metric::Keystroke> = Vec::new();
+
+  // Index of the next press by the same finger, found going backwards
+  let mut next_press = vec![None; presses.len()];
+  let mut latest: HashMap<i16, usize> = HashMap::new();
+  for (i, press) in presses.iter().enumerate().rev() {
+    next_press[i] = latest.insert(press.key.finger, i);
   }
 
-  let mut total_dist = 0.0;
-  let mut total_switches = 0;
+  for (i, press) in presses.iter().enumerate() {
+    let finger = press.key.finger as usize;
+    if press.key.finger < 0 || finger >= lay.homes.len() {
+      continue;
+    }
+    sim.replay_press(press, next_press[i].map(|n| &presses[n]));
 
-  // Next press must start after previous ends
-  let mut time_end_prev_press = 0;
-  let mut total_time = 0;
+    let ks = sim.keystroke(press.ch, press.key, finger, press.down, press.up, press.modifier);
+    if press.modifier {
+      mods.push(ks);
+      continue;
+    }
 
-  // What hand(s) the previous press used
-  let mut prev_left = false;
-  let mut prev_right = false;
+    // Modifiers let go of before this press were pressed on their own
+    let (held, alone): (Vec<_>, Vec<_>) = mods.drain(..).partition(|m| m.end >= press.down);
+    for m in alone {
+      sim.count_combo(&[m]);
+    }
+    let mut keystrokes = held;
+    keystrokes.push(ks);
+    sim.count_combo(&keystrokes);
+    sim.total_chars += 1;
+  }
+  for m in mods {
+    sim.count_combo(&[m]);
+  }
 
-  // Inclusive inner bounds for which "hand" each finger is on.
-  // If a finger is homed on space, assume it stays on space and the
-  // fingers on either side are left and right
-  let mut right_start = (lay.homes.len() / 2) as i16;
-  let mut left_end = right_start;
-  let space_key = lay.char_keys.get(&' ').unwrap().key;
-  if space_key.is_home {
-    assert!(space_key.finger != 0 && space_key.finger != (lay.homes.len() as i16) - 1,
-            "Layouts with a finger homed on the spacebar currently need to use one that isn't the leftmost or rightmost finger");
+  let text = keylog::typed_text(presses);
+  let mut tl = sim.finish(&text);
+  if let Some(cfg) = &model.effort {
+    tl.effort = effort::effort(&text, lay, cfg);
+  }
+  tl
+}
 
-    right_start = space_key.finger + 1;
-    left_end = space_key.finger - 1;
+// What each stroke of string cost to type, in order. Chars the layout
+// can't type are left out
+pub fn stroke_costs(string: &str, lay: &layout::Layout, model: &model::Model) -> Vec<StrokeCost> {
+  let mut sim = Sim::new(false, lay, model);
+  sim.costs = Some(Vec::new());
+  simulate(&mut sim, string, lay, model);
+  sim.costs.unwrap_or_default()
+}
+
+// Everything the fingers do while typing string, in order of time
+pub fn events(string: &str, lay: &layout::Layout, model: &model::Model) -> std::vec::IntoIter<event::Event> {
+  let mut sim = Sim::new(false, lay, model);
+  sim.events = Some(Vec::new());
+  simulate(&mut sim, string, lay, model);
+  if !sim.stay {
+    sim.return_home(&[]);
   }
 
+  let mut events = sim.events.unwrap_or_default();
+  // Fingers going home are only added once another press needs to
+  // know where they are, so they can be out of order
+  events.sort_by_key(|e| e.time);
+  events.into_iter()
+}
+
+fn simulate(sim: &mut Sim, string: &str, lay: &layout::Layout, model: &model::Model) {
+  let mut errors = model.errors.as_ref().and_then(|e| model::ErrorState::new(e, lay));
+  let mut shift = model::ShiftState::new(&model.shift, lay);
+
   // Each loop finishes moves fingers from last move back home, then
-  // moves fingers to keys necessary to input c
-  for c in string.chars() {
-    let mut used_keys = Vec::new();
-    let combo = match lay.char_keys.get(&c) {
-      Some(co) => co,
+  // moves fingers to keys necessary to input the stroke
+  for stroke in input::strokes(string, lay) {
+    let combos = match stroke.combos {
+      Some(cs) => cs,
+      None => {
+        sim.total_unmapped += 1;
+        continue;
+      }
+    };
+    sim.total_chars += stroke.chars;
+    let before = (sim.time_start_prev_press, sim.distance.total, sim.same_finger.total);
+
+    // Dead keys and compose sequences take several combos per char,
+    // and the shift model can add presses
+    for combo in shift.combos(string, stroke.idx, stroke.ch, &combos).iter() {
+      if let Some(errs) = errors.as_mut() {
+        if let Some((wrong, correction)) = errs.roll(combo, lay) {
+          sim.press(&wrong, None);
+          sim.press(&correction, None);
+          sim.finger_errors[main_finger(lay, combo)] += 1;
+        }
+      }
+
+      sim.press(combo, stroke.ch);
+    }
+
+    if let Some(costs) = sim.costs.as_mut() {
+      costs.push(StrokeCost {
+        idx: stroke.idx,
+        chars: stroke.chars,
+        time: sim.time_start_prev_press - before.0,
+        dist: sim.distance.total - before.1,
+        same_finger: sim.same_finger.total - before.2,
+      });
+    }
+  }
+}
+
+// Length of the path to gesture type word, through the centers of the
+// keys of its letters and digits. None if it has none or the layout
+// can't type one of them
+pub fn swipe_len(word: &str, lay: &layout::Layout) -> Option<f32> {
+  let mut len = 0.0;
+  let mut prev: Option<layout::Pos> = None;
+
+  for c in word.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase) {
+    let center = lay.char_keys.get(&c)?.key.center();
+    if let Some(p) = prev {
+      len += move_dist(&p, &center);
+    }
+    prev = Some(center);
+  }
+
+  prev.map(|_| len)
+}
+
+// Sideways distance of key from home, and how many rows away it is
+fn home_offset(home: &layout::Key, key: &layout::Key) -> (f32, i32) {
+  let (h, k) = (home.center(), key.center());
+  (k.x - h.x, (k.y - h.y).round() as i32)
+}
+
+// The sideways offset from home of each finger's own column in each
+// row it has keys in. Its other keys in that row are in stretch
+// columns, like g and h. A row's stagger is taken from fingers with
+// only one key in it, and the own column is the key closest to it
+pub fn own_columns(lay: &layout::Layout) -> HashMap<(usize, i32), f32> {
+  let mut offsets: HashMap<(usize, i32), Vec<f32>> = HashMap::new();
+  for key in lay.keys.iter().chain(lay.mod_map.values()) {
+    let finger = key.finger as usize;
+    if key.finger < 0 || finger >= lay.homes.len() || std::ptr::eq(lay.homes[finger], &layout::DUMMY_KEY) {
+      continue;
+    }
+    let (dx, dy) = home_offset(lay.homes[finger], key);
+    offsets.entry((finger, dy)).or_default().push(dx);
+  }
+
+  let stagger = |hand: layout::Hand, dy: i32| {
+    let mut singles: Vec<f32> = offsets
+      .iter()
+      .filter(|((f, row), dxs)| *row == dy && dxs.len() == 1 && lay.fingers.get(*f).is_some_and(|f| f.hand == hand))
+      .map(|(_, dxs)| dxs[0])
+      .collect();
+    singles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    singles.get(singles.len() / 2).copied().unwrap_or(0.0)
+  };
+
+  let mut res = HashMap::new();
+  for ((finger, dy), dxs) in offsets.iter() {
+    let hand = match lay.fingers.get(*finger) {
+      Some(f) => f.hand,
       None => continue,
     };
+    let s = stagger(hand, *dy);
+    let own = dxs.iter().min_by(|a, b| (*a - s).abs().partial_cmp(&(*b - s).abs()).unwrap());
+    res.insert((*finger, *dy), *own.unwrap());
+  }
+  res
+}
+
+// The row of key for the finger pressing it, and whether it's in a
+// stretch column. own_columns comes from own_columns()
+pub fn key_place(lay: &layout::Layout, own_columns: &HashMap<(usize, i32), f32>, finger: usize, key: &layout::Key) -> (Row, bool) {
+  let thumb = lay.fingers.get(finger).is_some_and(|f| f.is_thumb());
+  let (dx, dy) = home_offset(lay.homes[finger], key);
+  let row = match dy {
+    _ if thumb => Row::Thumb,
+    i32::MIN..=-2 => Row::Number,
+    -1 => Row::Top,
+    0 => Row::Home,
+    _ => Row::Bottom,
+  };
+
+  let own = own_columns.get(&(finger, dy));
+  let stretch = !thumb && own.is_some_and(|own| (dx - own).abs() > STRETCH_EPSILON);
+  (row, stretch)
+}
+
+// State of a simulation in progress
+struct Sim<'l> {
+  lay: &'l layout::Layout<'l>,
+  gen_anim: bool,
+  stay: bool, // Fingers stay on the last key they pressed
+  key_pitch: f32,
+  timing: model::Timing,
+  fingers: Vec<Vec<Keyframe>>,
+  finger_errors: Vec<u32>,
+  finger_move_time: Vec<i32>,
+  finger_reach: Vec<f32>,
+
+  distance: metric::Distance,
+  usage: metric::Usage,
+  switches: metric::Switches,
+  same_finger: metric::SameFinger,
+  metrics: Vec<Box<dyn Metric>>, // On top of the built in ones above
+  events: Option<Vec<event::Event>>, // Only kept if asked for
+  costs: Option<Vec<StrokeCost>>,
+
+  total_strokes: u32,
+  total_chars: u32,
+  total_unmapped: u32,
+  row_counts: [u32; 5],
+  stretch_presses: u32,
+  // Sideways distance to the own column of each finger and row
+  own_columns: HashMap<(usize, i32), f32>,
+
+  // Next press must start after previous ends
+  time_end_prev_press: i32,
+  total_time: i32,
+
+  critical: CriticalPath,
+  time_start_prev_press: i32,
+  prev_finger: Option<usize>, // Main finger of the previous press
+
+  // What hand(s) the previous press used
+  prev_left: bool,
+  prev_right: bool,
+}
+
+impl<'l> Sim<'l> {
+  fn new(gen_anim: bool, lay: &'l layout::Layout<'l>, model: &model::Model) -> Self {
+    let mobile = model.mobile.as_ref();
+    let mut fingers: Vec<Vec<Keyframe>> = vec![Default::default(); lay.homes.len()];
+
+    for i in 0..lay.homes.len() {
+      fingers[i].push(Keyframe {
+        pos: layout::Pos {
+          x: lay.homes[i].pos.x,
+          y: lay.homes[i].pos.y,
+        },
+        time: 0,
+        start_press: false,
+        on_char: lay.homes[i].pressed,
+      });
+    }
+
+    Sim {
+      lay,
+      gen_anim,
+      stay: mobile.is_some(),
+      key_pitch: mobile.map_or(KEY_PITCH, |m| m.key_pitch),
+      timing: model.timing.clone(),
+      fingers,
+      finger_errors: vec![0; lay.homes.len()],
+      finger_move_time: vec![0; lay.homes.len()],
+      finger_reach: vec![0.0; lay.homes.len()],
+      distance: metric::Distance::new(lay.homes.len()),
+      usage: metric::Usage::new(lay.homes.len()),
+      switches: metric::Switches::default(),
+      same_finger: metric::SameFinger::default(),
+      metrics: model.metrics.map_or(Vec::new(), |make| make()),
+      events: None,
+      costs: None,
+      total_strokes: 0,
+      total_chars: 0,
+      total_unmapped: 0,
+      row_counts: [0; 5],
+      stretch_presses: 0,
+      own_columns: own_columns(lay),
+      time_end_prev_press: 0,
+      total_time: 0,
+      critical: CriticalPath::default(),
+      time_start_prev_press: 0,
+      prev_finger: None,
+      prev_left: false,
+      prev_right: false,
+    }
+  }
+
+  // Move fingers to and press every key in combo, which types ch
+  fn press(&mut self, combo: &layout::Combo, ch: Option<char>) {
+    let lay = self.lay;
+    let gen_anim = self.gen_anim;
+    let mut keystrokes = Vec::new();
+
+    let mut used_keys = Vec::new();
     let main_key = combo.key;
 
     let mut time_end_press = 0;
@@ -156,8 +597,11 @@ pub fn gen_timeline<'a>(string: &str, gen_anim: bool, lay: &'a layout::Layout) -
 
     let mut max_dur = 0;
     let mut min_start = 0;
+    // Fingers that set min_start and max_dur
+    let mut start_finger = None;
+    let mut dur_finger = None;
 
-    let mut main_findex = main_key.finger as usize;
+    let main_findex = main_finger(lay, combo);
 
     if combo.mods.is_some() {
       let mods = combo.mods.as_ref().unwrap();
@@ -166,70 +610,102 @@ pub fn gen_timeline<'a>(string: &str, gen_anim: bool, lay: &'a layout::Layout) -
       for modifier in mods {
         let findex = modifier.finger as usize;
 
-        if findex == main_findex {
-          // TODO: This is really dumb and it will need to be changed for mulit-modifier combos
-          for i in 0..lay.homes.len() {
-            if i != findex {
-              main_findex = i;
-              break;
-            }
-          }
-        }
-
         used_keys.push(findex);
-        let prev = fingers[findex].last().unwrap();
+        let prev = self.fingers[findex].last().unwrap();
 
-        let dur = move_time(&prev.pos, &modifier.pos);
-        max_dur = max_dur.max(dur);
-        min_start = min_start.max(prev.time);
-        this_left = this_left || (findex as i16) <= left_end;
-        this_right = this_right || (findex as i16) >= right_start;
+        let dur = self.timing.move_time(findex, &prev.pos, &modifier.pos);
+        if dur > max_dur {
+          max_dur = dur;
+          dur_finger = Some(findex);
+        }
+        if prev.time > min_start {
+          min_start = prev.time;
+          start_finger = Some(findex);
+        }
+        this_left = this_left || uses_hand(lay, findex, layout::Hand::Left);
+        this_right = this_right || uses_hand(lay, findex, layout::Hand::Right);
       }
     }
 
     used_keys.push(main_findex);
-    let main_home = lay.homes[main_findex];
-    let main_prev = *fingers[main_findex].last().unwrap();
+    let main_home = if self.stay { main_key } else { lay.homes[main_findex] };
+    let main_prev = *self.fingers[main_findex].last().unwrap();
 
-    this_left = this_left || (main_findex as i16) <= left_end;
-    this_right = this_right || (main_findex as i16) >= right_start;
+    this_left = this_left || uses_hand(lay, main_findex, layout::Hand::Left);
+    this_right = this_right || uses_hand(lay, main_findex, layout::Hand::Right);
 
-    max_dur = max_dur.max(move_time(&main_prev.pos, &main_key.pos));
-    min_start = min_start.max(main_prev.time);
+    let main_dur = self.timing.move_time(main_findex, &main_prev.pos, &main_key.pos);
+    if main_dur >= max_dur {
+      max_dur = main_dur;
+      dur_finger = None;
+    }
+    if main_prev.time >= min_start {
+      min_start = main_prev.time;
+      start_finger = Some(main_findex);
+    }
 
     // Finish the moves of fingers this key combo doesn't use
-    return_home(&used_keys, gen_anim, &mut fingers, lay);
+    if !self.stay {
+      self.return_home(&used_keys);
+    }
 
     // If this move uses a hand that the previous move used, don't
     // start moving until the previous press finishes
-    if (this_left && prev_left) || (this_right && prev_right) {
-      min_start = min_start.max(time_end_prev_press);
-    } else {
-      total_switches += 1;
+    if (this_left && self.prev_left) || (this_right && self.prev_right) {
+      let same_hand_start = self.time_end_prev_press + self.timing.same_hand;
+      if same_hand_start > min_start {
+        min_start = same_hand_start;
+        start_finger = None;
+      }
     }
-    let min_press = time_end_prev_press.max(min_start + max_dur);
+    let min_press = self.time_end_prev_press.max(min_start + max_dur);
+
+    let (cause, cause_finger) = if self.time_end_prev_press >= min_start + max_dur {
+      (Cause::PressGap, self.prev_finger.unwrap_or(main_findex))
+    } else if let Some(f) = dur_finger {
+      (Cause::Modifier, f)
+    } else if let Some(f) = start_finger {
+      (Cause::Travel, f)
+    } else {
+      (Cause::SameHand, main_findex)
+    };
+    let time_start_press = min_press + self.timing.press_gap;
+    self.critical.count(cause, cause_finger, time_start_press - self.time_start_prev_press);
+    self.time_start_prev_press = time_start_press;
+    self.prev_finger = Some(main_findex);
 
     if combo.mods.is_some() {
       let mods = combo.mods.as_ref().unwrap();
       // Add keyframes for modifiers
       for modifier in mods {
         let mod_findex = modifier.finger as usize;
-        let (this_end_press, this_end_move) = calc_keyframes(
-          &fingers[mod_findex].last().unwrap().clone(),
+        let mod_prev = *self.fingers[mod_findex].last().unwrap();
+        let mod_home = if self.stay { modifier } else { lay.homes[mod_findex] };
+        let moves = (
+          self.timing.move_time(mod_findex, &mod_prev.pos, &modifier.pos),
+          self.timing.move_time(mod_findex, &modifier.pos, &mod_home.pos),
+        );
+        let (this_start_move, this_end_press, this_end_move) = calc_keyframes(
+          &mod_prev,
           modifier,
-          lay.homes[mod_findex],
+          moves,
           min_start,
           min_press,
-          gen_anim,
-          &mut fingers[mod_findex],
+          &self.timing,
+          gen_anim.then_some(&mut self.fingers[mod_findex]),
         );
 
         time_end_press = time_end_press.max(this_end_press);
         time_end_move = time_end_move.max(this_end_move);
 
+        self.travel(mod_findex, &mod_prev.pos, &modifier.pos);
+        self.count_press(mod_findex, modifier);
+        self.record_press(mod_findex, modifier, None, &mod_prev.pos, this_start_move, this_end_press);
+        keystrokes.push(self.keystroke(ch, modifier, mod_findex, this_end_press - self.timing.press_dur, this_end_press, true));
+
         if !gen_anim {
           // The animation-less mode still relies on the last keyframe
-          fingers[mod_findex][0] = Keyframe {
+          self.fingers[mod_findex][0] = Keyframe {
             pos: modifier.pos,
             time: this_end_press,
             start_press: false,
@@ -240,19 +716,23 @@ pub fn gen_timeline<'a>(string: &str, gen_anim: bool, lay: &'a layout::Layout) -
     }
 
     // Add main frames
-    let (this_end_press, this_end_move) = calc_keyframes(
+    let moves = (
+      self.timing.move_time(main_findex, &main_prev.pos, &main_key.pos),
+      self.timing.move_time(main_findex, &main_key.pos, &main_home.pos),
+    );
+    let (this_start_move, this_end_press, this_end_move) = calc_keyframes(
       &main_prev,
       main_key,
-      main_home,
+      moves,
       min_start,
       min_press,
-      gen_anim,
-      &mut fingers[main_findex],
+      &self.timing,
+      gen_anim.then_some(&mut self.fingers[main_findex]),
     );
 
     if !gen_anim {
       // The animation-less mode still relies on the last keyframe
-      fingers[main_findex][0] = Keyframe {
+      self.fingers[main_findex][0] = Keyframe {
         pos: main_key.pos,
         time: this_end_press,
         start_press: false,
@@ -263,62 +743,282 @@ pub fn gen_timeline<'a>(string: &str, gen_anim: bool, lay: &'a layout::Layout) -
     time_end_press = time_end_press.max(this_end_press);
     time_end_move = time_end_move.max(this_end_move);
 
-    // Add to stats
-    // For now this only includes main finger usage/movement
-    finger_usage_cnt[main_findex] += 1;
-    total_dist += move_dist(&main_prev.pos, &main_key.pos);
-    total_dist += move_dist(&main_key.pos, &main_home.pos);
+    self.travel(main_findex, &main_prev.pos, &main_key.pos);
+    self.count_press(main_findex, main_key);
+    self.record_press(main_findex, main_key, ch, &main_prev.pos, this_start_move, this_end_press);
+    keystrokes.push(self.keystroke(ch, main_key, main_findex, this_end_press - self.timing.press_dur, this_end_press, false));
+
+    self.count_combo(&keystrokes);
+
+    self.prev_left = this_left;
+    self.prev_right = this_right;
+
+    self.time_end_prev_press = time_end_press;
+    self.total_time = time_end_move;
+  }
+
+  fn count_combo(&mut self, keystrokes: &[metric::Keystroke]) {
+    self.usage.keystrokes(keystrokes);
+    self.switches.keystrokes(keystrokes);
+    self.same_finger.keystrokes(keystrokes);
+    for m in self.metrics.iter_mut() {
+      m.keystrokes(keystrokes);
+    }
+    self.total_strokes += 1;
+  }
+
+  // Move finger to the key of a recorded press, arriving as it goes
+  // down, and then back home if there's time before its next press
+  fn replay_press(&mut self, press: &keylog::KeyPress, next: Option<&keylog::KeyPress>) {
+    let finger = press.key.finger as usize;
+    let key = press.key;
+    let prev = *self.fingers[finger].last().unwrap();
+    let mut frames = Vec::new();
+
+    // Presses can't overlap the finger's last one
+    let down = press.down.max(prev.time);
+    let up = press.up.max(down);
+    let dur = self.timing.move_time(finger, &prev.pos, &key.pos);
+    let start_move = prev.time.max(down - dur);
+    if start_move != prev.time {
+      frames.push(Keyframe {
+        time: start_move,
+        ..prev
+      });
+    }
+    if start_move + dur < down {
+      frames.push(Keyframe {
+        pos: key.pos,
+        time: start_move + dur,
+        start_press: false,
+        on_char: key.pressed,
+      });
+    }
+    frames.push(Keyframe {
+      pos: key.pos,
+      time: down,
+      start_press: true,
+      on_char: key.pressed,
+    });
+    frames.push(Keyframe {
+      pos: key.pos,
+      time: up,
+      start_press: false,
+      on_char: key.pressed,
+    });
+    self.travel(finger, &prev.pos, &key.pos);
+    self.count_press(finger, key);
+
+    let home = if self.stay { key } else { self.lay.homes[finger] };
+    let back = self.timing.move_time(finger, &key.pos, &home.pos);
+    let has_time = match next {
+      Some(n) => up + back + self.timing.move_time(finger, &home.pos, &n.key.pos) <= n.down,
+      None => true,
+    };
+    if back > 0 && has_time {
+      frames.push(Keyframe {
+        pos: home.pos,
+        time: up + back,
+        start_press: false,
+        on_char: home.pressed,
+      });
+      self.travel(finger, &key.pos, &home.pos);
+    }
+
+    let last = *frames.last().unwrap();
+    self.total_time = self.total_time.max(last.time);
+    if self.gen_anim {
+      self.fingers[finger].extend(frames);
+    } else {
+      self.fingers[finger][0] = last;
+    }
+  }
 
-    prev_left = this_left;
-    prev_right = this_right;
+  // Count a move of finger, which is only counted once it happens, so
+  // fingers pressing several keys in a row don't return in between
+  fn travel(&mut self, finger: usize, from: &layout::Pos, to: &layout::Pos) {
+    self.distance.travel(finger, from, to);
+    for m in self.metrics.iter_mut() {
+      m.travel(finger, from, to);
+    }
+    self.finger_move_time[finger] += self.timing.move_time(finger, from, to);
+  }
 
-    time_end_prev_press = time_end_press;
-    total_time = time_end_move;
+  fn record(&mut self, time: i32, kind: event::EventKind, finger: usize, key: &layout::Key, ch: Option<char>) {
+    if let Some(events) = self.events.as_mut() {
+      events.push(event::Event {
+        time,
+        kind,
+        finger,
+        key: key.visual.name.clone(),
+        ch,
+      });
+    }
   }
 
-  // Finish the last move
-  if gen_anim {
-    return_home(&Vec::new(), gen_anim, &mut fingers, lay);
+  // Add the events of finger moving from from to key, if it has to,
+  // and pressing it
+  fn record_press(&mut self, finger: usize, key: &layout::Key, ch: Option<char>, from: &layout::Pos, start_move: i32, end_press: i32) {
+    if self.events.is_none() {
+      return;
+    }
+    if from.x != key.pos.x || from.y != key.pos.y {
+      self.record(start_move, event::EventKind::MoveStart, finger, key, ch);
+      let end_move = start_move + self.timing.move_time(finger, from, &key.pos);
+      self.record(end_move, event::EventKind::MoveEnd, finger, key, ch);
+    }
+    self.record(end_press - self.timing.press_dur, event::EventKind::KeyDown, finger, key, ch);
+    self.record(end_press, event::EventKind::KeyUp, finger, key, ch);
   }
 
-  Timeline {
-    fingers,
-    finger_counts: finger_usage_cnt,
-    total_time,
-    total_dist,
-    total_words: string.split_whitespace().count() as u32,
-    total_chars: string.len() as u32,
-    total_switches,
+  fn keystroke<'k>(&self, ch: Option<char>, key: &'k layout::Key, finger: usize, start: i32, end: i32, modifier: bool) -> metric::Keystroke<'k> {
+    let info = self.lay.fingers.get(finger);
+    metric::Keystroke {
+      ch,
+      key,
+      finger,
+      hand: info.map(|f| f.hand),
+      thumb: info.is_some_and(|f| f.is_thumb()),
+      start,
+      end,
+      modifier,
+    }
+  }
+
+  fn count_press(&mut self, finger: usize, key: &layout::Key) {
+    let home = self.lay.homes[finger];
+    let reach = move_dist(&home.pos, &key.pos);
+    self.finger_reach[finger] = self.finger_reach[finger].max(reach);
+
+    let (row, stretch) = key_place(self.lay, &self.own_columns, finger, key);
+    self.row_counts[row as usize] += 1;
+    if stretch {
+      self.stretch_presses += 1;
+    }
+  }
+
+  // Returns fingers to their homes unless they are in ignore.
+  fn return_home(&mut self, ignore: &[usize]) {
+    let lay = self.lay;
+    for i in 0..lay.homes.len() {
+      if ignore.contains(&i) {
+        continue;
+      }
+      let home = lay.homes[i];
+      let prev = *self.fingers[i].last().unwrap();
+      if move_dist(&prev.pos, &home.pos) < 0.1 {
+        continue;
+      }
+      self.travel(i, &prev.pos, &home.pos);
+
+      let return_move_end = prev.time + self.timing.move_time(i, &prev.pos, &home.pos);
+      self.record(prev.time, event::EventKind::MoveStart, i, home, None);
+      self.record(return_move_end, event::EventKind::MoveEnd, i, home, None);
+
+      let frame = Keyframe {
+        pos: home.pos,
+        time: return_move_end,
+        start_press: false,
+        on_char: home.pressed,
+      };
+      if self.gen_anim {
+        self.fingers[i].push(frame);
+      } else {
+        self.fingers[i][0] = frame;
+      }
+    }
+  }
+
+  // What every metric measured, the built in ones first
+  fn metric_values(&self) -> Vec<(String, f32)> {
+    let built_in: [&dyn Metric; 4] = [&self.distance, &self.usage, &self.switches, &self.same_finger];
+    built_in.iter().copied().chain(self.metrics.iter().map(|m| m.as_ref())).flat_map(|m| m.values()).collect()
+  }
+
+  fn finish(mut self, string: &str) -> Timeline {
+    // Finish the last move
+    if !self.stay {
+      self.return_home(&[]);
+    }
+    let metrics = self.metric_values();
+
+    Timeline {
+      fingers: self.fingers,
+      finger_info: self.lay.fingers.clone(),
+      finger_counts: self.usage.per_finger,
+      finger_errors: self.finger_errors,
+      finger_dist: self.distance.per_finger,
+      finger_move_time: self.finger_move_time,
+      finger_press_time: self.usage.press_time,
+      finger_reach: self.finger_reach,
+      total_time: self.total_time,
+      total_dist: self.distance.total,
+      total_words: string.split_whitespace().count() as u32,
+      total_chars: self.total_chars,
+      total_unmapped: self.total_unmapped,
+      total_strokes: self.total_strokes,
+      total_switches: self.switches.total,
+      row_counts: self.row_counts,
+      stretch_presses: self.stretch_presses,
+      total_swipe: 0.0,
+      swipe_words: 0,
+      key_pitch: self.key_pitch,
+      effort: effort::Effort::default(),
+      critical: self.critical,
+      metrics,
+    }
   }
 }
 
-// Given the starting frame, what to press, where to return, add
-// the necessary frames for the whole move
+// The finger that presses the main key of combo. If a modifier is on
+// the same finger, another one presses it
+fn main_finger(lay: &layout::Layout, combo: &layout::Combo) -> usize {
+  let mut main = combo.key.finger as usize;
+  for modifier in combo.mods.iter().flatten() {
+    if modifier.finger as usize == main {
+      // TODO: This is really dumb and it will need to be changed for mulit-modifier combos
+      if let Some(i) = (0..lay.homes.len()).find(|i| *i != main) {
+        main = i;
+      }
+    }
+  }
+  main
+}
+
+// Whether a press by finger counts as using hand when deciding if
+// presses alternate. Thumbs are ignored
+pub fn uses_hand(lay: &layout::Layout, finger: usize, hand: layout::Hand) -> bool {
+  match lay.fingers.get(finger) {
+    Some(f) => f.hand == hand && !f.is_thumb(),
+    None => false,
+  }
+}
+
+// Given the starting frame, what to press, and how long the moves to
+// it and back home take, add the necessary frames for the whole move
 // min_start is the earliest the finger can start moving to the key
 // min_press is the earliest the key can start being pressed
-// return the time the press ends and when the move ends
+// return the time the move to the key starts, the press ends and the
+// move back ends
 fn calc_keyframes(
   prev: &Keyframe,
   press_key: &layout::Key,
-  home_key: &layout::Key,
+  (dur_start_move, dur_end_move): (i32, i32),
   min_start: i32,
   min_press: i32,
-  push_frames: bool,
-  frames: &mut Vec<Keyframe>,
-) -> (i32, i32) {
-  let min_press = min_press + PRESS_GAP;
+  timing: &model::Timing,
+  frames: Option<&mut Vec<Keyframe>>,
+) -> (i32, i32, i32) {
+  let min_press = min_press + timing.press_gap;
 
-  let dur_start_move = move_time(&prev.pos, &press_key.pos);
   let time_start_move = prev.time.max(min_start).max(min_press - dur_start_move);
   let time_start_press = min_press.max(min_start + dur_start_move);
-  let dur_end_move = move_time(&press_key.pos, &home_key.pos);
+  let time_end_press = time_start_press + timing.press_dur;
 
-  if !push_frames {
-    return (
-      time_start_press + PRESS_DUR,
-      time_start_press + PRESS_DUR + dur_end_move,
-    );
-  }
+  let frames = match frames {
+    Some(f) => f,
+    None => return (time_start_move, time_end_press, time_end_press + dur_end_move),
+  };
 
   // Avoid duplicating end frame of previous move
   if time_start_move != prev.time {
@@ -351,49 +1051,28 @@ fn calc_keyframes(
   // End pressing
   frames.push(Keyframe {
     pos: press_key.pos,
-    time: time_start_press + PRESS_DUR,
+    time: time_end_press,
     start_press: false,
     on_char: press_key.pressed,
   });
 
   (
-    time_start_press + PRESS_DUR,
-    time_start_press + PRESS_DUR + dur_end_move,
+    time_start_move,
+    time_end_press,
+    time_end_press + dur_end_move,
   )
 }
 
-// Returns fingers to their homes unless they are in ignore.
-fn return_home<'a>(ignore: &Vec<usize>, animate: bool, fingers: &mut Vec<Vec<Keyframe>>, lay: &'a layout::Layout) {
-  for i in 0..lay.homes.len() {
-    if ignore.contains(&i) {
-      continue;
-    }
-    let home = lay.homes[i];
-    let prev = fingers[i].last().unwrap();
-    if move_dist(&prev.pos, &home.pos) < 0.1 {
-      continue;
-    }
-
-    let return_move_end = prev.time + move_time(&prev.pos, &home.pos);
-
-    let frame = Keyframe {
-        pos: home.pos,
-        time: return_move_end,
-        start_press: false,
-        on_char: home.pressed,
-    };
-    if animate {
-      fingers[i].push(frame);
-    } else {
-      fingers[i][0] = frame;
-    }
-  }
-}
-
 pub fn print_timeline(tl: &Timeline) {
   for i in 0..tl.fingers.len() {
-    println!("Finger {}", i);
+    match tl.finger_info.get(i) {
+      Some(f) => println!("Finger {} ({:?} {:?})", i, f.hand, f.kind),
+      None => println!("Finger {}", i),
+    }
     println!("  Usage %: {}", tl.usage_percent(i));
+    if tl.total_errors() > 0 {
+      println!("  Error %: {:.2}", tl.error_percent(i));
+    }
 
     for kf in &tl.fingers[i] {
       println!(
@@ -413,35 +1092,192 @@ pub fn print_timeline(tl: &Timeline) {
     println!("]");
   }
 
+  println!("{}", finger_table(tl));
+  if tl.critical.counts.iter().sum::<u32>() > 0 {
+    println!("{}", critical_table(tl));
+  }
   println!("{}", stats_string(tl));
 }
 
+fn cause_name(cause: Cause) -> &'static str {
+  match cause {
+    Cause::PressGap => "Press gap",
+    Cause::SameHand => "Same hand",
+    Cause::Travel => "Travel",
+    Cause::Modifier => "Modifier",
+  }
+}
+
+// What the presses waited on: how many each cause held up, the time
+// between presses it accounts for, and the finger behind most of it
+pub fn critical_table(tl: &Timeline) -> String {
+  let cp = &tl.critical;
+  let mut res = format!(
+    "{:<10} {:>8} {:>9} {:>7} {:>7}",
+    "Cause", "Presses", "Time", "% Time", "Finger"
+  );
+  for cause in CAUSES.iter() {
+    let finger = match cp.worst_finger(*cause) {
+      Some(f) => f.to_string(),
+      None => "-".to_string(),
+    };
+    res.push_str(&format!(
+      "\n{:<10} {:>8} {:>7}ms {:>6.1}% {:>7}",
+      cause_name(*cause),
+      cp.counts[*cause as usize],
+      cp.time[*cause as usize],
+      cp.percent(*cause),
+      finger
+    ));
+  }
+  res
+}
+
+fn finger_name(tl: &Timeline, i: usize) -> (String, String) {
+  match tl.finger_info.get(i) {
+    Some(f) => (format!("{:?}", f.hand), format!("{:?}", f.kind)),
+    None => ("-".to_string(), "-".to_string()),
+  }
+}
+
+// Load on each finger: presses, distance, time moving and pressing,
+// and the farthest it reached from home
+pub fn finger_table(tl: &Timeline) -> String {
+  let mut res = format!(
+    "{:<7} {:<6} {:<7} {:>8} {:>6} {:>9} {:>9} {:>9} {:>9}",
+    "Finger", "Hand", "Kind", "Presses", "Usage", "Dist", "Moving", "Pressing", "Reach"
+  );
+  for i in 0..tl.finger_counts.len() {
+    let (hand, kind) = finger_name(tl, i);
+    res.push_str(&format!(
+      "\n{:<7} {:<6} {:<7} {:>8} {:>5}% {:>8.2}u {:>7}ms {:>7}ms {:>8.2}u",
+      i,
+      hand,
+      kind,
+      tl.finger_counts[i],
+      tl.usage_percent(i),
+      tl.finger_dist[i],
+      tl.finger_move_time[i],
+      tl.finger_press_time[i],
+      tl.finger_reach[i]
+    ));
+  }
+  res
+}
+
+// Write the finger table as CSV, with distances in u and mm
+pub fn write_finger_csv(tl: &Timeline, path: &str) -> csv::Result<()> {
+  let mut writer = csv::Writer::from_path(path)?;
+  writer.write_record([
+    "finger",
+    "hand",
+    "kind",
+    "presses",
+    "errors",
+    "usage_percent",
+    "dist_u",
+    "dist_mm",
+    "move_ms",
+    "press_ms",
+    "reach_u",
+  ])?;
+
+  for i in 0..tl.finger_counts.len() {
+    let (hand, kind) = finger_name(tl, i);
+    writer.write_record(&[
+      i.to_string(),
+      hand,
+      kind,
+      tl.finger_counts[i].to_string(),
+      tl.finger_errors[i].to_string(),
+      tl.usage_percent(i).to_string(),
+      tl.finger_dist[i].to_string(),
+      (tl.finger_dist[i] * tl.key_pitch).to_string(),
+      tl.finger_move_time[i].to_string(),
+      tl.finger_press_time[i].to_string(),
+      tl.finger_reach[i].to_string(),
+    ])?;
+  }
+
+  writer.flush()?;
+  Ok(())
+}
+
 pub fn stats_string(tl: &Timeline) -> String {
-  format!(
+  let mut effort_line = String::new();
+  if tl.effort.triads > 0 {
+    let per = |e: f32| e / (tl.effort.triads as f32);
+    effort_line = format!(
+      "Effort per triad: {:.3} (base {:.3}, penalty {:.3}, path {:.3})\n",
+      tl.effort.per_triad(),
+      per(tl.effort.base),
+      per(tl.effort.penalty),
+      per(tl.effort.path)
+    );
+  }
+
+  let mut res = format!(
     concat!(
       "Total distance covered: {}u\n",
       "                        {}mm\n",
       "                        {}m\n",
       "                        {}km\n",
+      "{}",
       "Distance per char: {}u\n",
+      "Strokes per char: {}\n",
+      "Strokes per word: {}\n",
       "Total time: {}s\n",
       "Total words: {}\n",
+      "Coverage: {:.2}%\n",
+      "Errors: {}\n",
       "% Alternating: {}%\n",
+      "% Thumbs: {}%\n",
+      "Rows: {:.1}% number, {:.1}% top, {:.1}% home, {:.1}% bottom, {:.1}% thumb\n",
+      "% Home row: {:.1}%\n",
+      "Hand balance: {:.1}% left, {:.1}% right\n",
+      "% Stretch columns: {:.1}%\n",
       "WPM: {}"
     ),
     tl.total_dist,
     tl.total_dist_mm(),
     tl.total_dist_m(),
     tl.total_dist_km(),
+    effort_line,
     tl.u_per_char(),
+    tl.strokes_per_char(),
+    tl.strokes_per_word(),
     tl.total_time / 1000,
     tl.total_words,
+    tl.coverage_percent(),
+    tl.total_errors(),
     tl.alternating_percent(),
+    tl.thumb_percent(),
+    tl.row_percent(Row::Number),
+    tl.row_percent(Row::Top),
+    tl.row_percent(Row::Home),
+    tl.row_percent(Row::Bottom),
+    tl.row_percent(Row::Thumb),
+    tl.home_row_percent(),
+    tl.hand_percent(layout::Hand::Left),
+    tl.hand_percent(layout::Hand::Right),
+    tl.stretch_percent(),
     tl.wpm()
-  )
+  );
+
+  if tl.swipe_words > 0 {
+    res.push_str(&format!(
+      "\nSwipe path per word: {}u ({}mm)",
+      tl.swipe_per_word(),
+      tl.swipe_per_word() * tl.key_pitch
+    ));
+  }
+  for (name, val) in tl.metrics.iter() {
+    res.push_str(&format!("\n{}: {}", name, val));
+  }
+  res
 }
 
-pub fn gen_timeline_file(path: &String, parallel: bool, lay: &layout::Layout) -> Timeline {
+pub fn read_file(path: &str) -> String {
   let mut file = match std::fs::File::open(path) {
     Ok(f) => f,
     Err(e) => panic!("file problem: {}", e),
@@ -450,14 +1286,18 @@ pub fn gen_timeline_file(path: &String, parallel: bool, lay: &layout::Layout) ->
   let mut text = String::new();
   file.read_to_string(&mut text).unwrap();
 
+  text
+}
+
+pub fn gen_timeline_text(text: &str, parallel: bool, lay: &layout::Layout, model: &model::Model) -> Timeline {
   if parallel {
-    gen_timeline_parallel(text.as_str(), lay)
+    gen_timeline_parallel(text, lay, model)
   } else {
-    gen_timeline(text.as_str(), false, lay)
+    gen_timeline_model(text, false, lay, model)
   }
 }
 
-fn gen_timeline_parallel<'a>(string: &'a str, lay: &layout::Layout) -> Timeline {
+fn gen_timeline_parallel<'a>(string: &'a str, lay: &layout::Layout, model: &model::Model) -> Timeline {
   // Split text into more consistent sizes than lines()
   let mut slices = Vec::new();
   let mut start = 0;
@@ -473,10 +1313,21 @@ fn gen_timeline_parallel<'a>(string: &'a str, lay: &layout::Layout) -> Timeline
 
   let coll: Vec<Timeline> = slices
     .par_iter()
-    .map(|line| gen_timeline(line, false, lay))
+    .enumerate()
+    .map(|(i, line)| gen_timeline_model(line, false, lay, &model.for_slice(i)))
     .collect();
 
-  let mut res = Timeline::default();
+  let mut res = Timeline {
+    finger_info: lay.fingers.clone(),
+    finger_counts: vec![0; lay.homes.len()],
+    finger_errors: vec![0; lay.homes.len()],
+    finger_dist: vec![0.0; lay.homes.len()],
+    finger_move_time: vec![0; lay.homes.len()],
+    finger_press_time: vec![0; lay.homes.len()],
+    finger_reach: vec![0.0; lay.homes.len()],
+    key_pitch: model.mobile.as_ref().map_or(KEY_PITCH, |m| m.key_pitch),
+    ..Default::default()
+  };
 
   // This is slightly inaccurate, w/ <1% error in total_time, and
   // ~0.05% error in distance covered, both overestimating.  TODO:
@@ -485,43 +1336,181 @@ fn gen_timeline_parallel<'a>(string: &'a str, lay: &layout::Layout) -> Timeline
   for tl in coll {
     for i in 0..lay.homes.len() {
       res.finger_counts[i] += tl.finger_counts[i];
+      res.finger_errors[i] += tl.finger_errors[i];
+      res.finger_dist[i] += tl.finger_dist[i];
+      res.finger_move_time[i] += tl.finger_move_time[i];
+      res.finger_press_time[i] += tl.finger_press_time[i];
+      res.finger_reach[i] = res.finger_reach[i].max(tl.finger_reach[i]);
     }
 
     res.total_time += tl.total_time;
     res.total_dist += tl.total_dist;
     res.total_words += tl.total_words;
     res.total_chars += tl.total_chars;
+    res.total_unmapped += tl.total_unmapped;
+    res.total_strokes += tl.total_strokes;
     res.total_switches += tl.total_switches;
+    for r in 0..ROWS.len() {
+      res.row_counts[r] += tl.row_counts[r];
+    }
+    res.stretch_presses += tl.stretch_presses;
+    res.effort.add(&tl.effort);
+    res.critical.add(&tl.critical);
+    if res.metrics.is_empty() {
+      res.metrics = tl.metrics.clone();
+    } else {
+      for (sum, (_, val)) in res.metrics.iter_mut().zip(tl.metrics.iter()) {
+        sum.1 += val;
+      }
+    }
+    res.total_swipe += tl.total_swipe;
+    res.swipe_words += tl.swipe_words;
   }
 
   res
 }
 
-pub fn compare_lines(path: &String, lay: &layout::Layout) -> Vec<(Timeline, String)> {
-  let mut file = match std::fs::File::open(path) {
-    Ok(f) => f,
-    Err(_) => panic!("file problem"),
-  };
+// What compare_lines ranks lines by
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RankBy {
+  Distance,
+  Time,
+  DistPerChar,
+  SameFinger, // Same finger bigrams
+  Alternation,
+  Effort, // Per triad
+}
 
-  let mut text = String::new();
-  file.read_to_string(&mut text).unwrap();
+pub fn parse_rank_by(s: &str) -> Option<RankBy> {
+  match s.to_ascii_lowercase().as_str() {
+    "distance" | "dist" => Some(RankBy::Distance),
+    "time" => Some(RankBy::Time),
+    "per-char" | "per-letter" => Some(RankBy::DistPerChar),
+    "sfb" | "same-finger" => Some(RankBy::SameFinger),
+    "alternation" | "alt" => Some(RankBy::Alternation),
+    "effort" => Some(RankBy::Effort),
+    _ => None,
+  }
+}
 
-  let mut heap = BinaryHeap::from_par_iter(
-    text
-      .par_lines()
-      .map(|line| (gen_timeline(line, false, lay), line.to_string())),
-  );
+// Which lines compare_lines keeps and how it orders them. Lengths are
+// in chars, and lines with the same value are ordered alphabetically
+#[derive(Clone)]
+pub struct Ranking {
+  pub by: RankBy,
+  pub count: usize,
+  pub ascending: bool,
+  pub min_len: usize,
+  pub max_len: Option<usize>,
+}
+
+impl Default for Ranking {
+  fn default() -> Self {
+    Ranking {
+      by: RankBy::Distance,
+      count: 100,
+      ascending: false,
+      min_len: 0,
+      max_len: None,
+    }
+  }
+}
+
+pub struct RankedLine {
+  pub line: String,
+  pub value: f32,
+  pub tl: Timeline,
+}
 
-  let mut res = Vec::new();
+pub fn same_finger_bigrams(tl: &Timeline) -> f32 {
+  tl.metric(metric::SAME_FINGER)
+}
 
-  for _ in 0..(heap.len().min(100)) {
-    res.push(heap.pop().unwrap());
+fn rank_value(tl: &Timeline, by: RankBy) -> f32 {
+  match by {
+    RankBy::Distance => tl.total_dist,
+    RankBy::Time => tl.total_time as f32,
+    RankBy::DistPerChar => tl.u_per_char(),
+    RankBy::SameFinger => same_finger_bigrams(tl),
+    RankBy::Alternation => tl.alternating_percent() as f32,
+    RankBy::Effort => tl.effort.per_triad(),
   }
+}
 
+// Simulate each line of text on its own and rank them. Blank lines
+// and lines with nothing typeable are left out
+pub fn compare_lines(text: &str, lay: &layout::Layout, model: &model::Model, ranking: &Ranking) -> Vec<RankedLine> {
+  let mut model = model.clone();
+  if ranking.by == RankBy::Effort && model.effort.is_none() {
+    model.effort = Some(effort::EffortConfig::default());
+  }
+
+  let mut res: Vec<RankedLine> = text
+    .par_lines()
+    .filter(|line| {
+      let len = line.chars().count();
+      !line.trim().is_empty() && len >= ranking.min_len && ranking.max_len.is_none_or(|max| len <= max)
+    })
+    .map(|line| {
+      let tl = gen_timeline_model(line, false, lay, &model);
+      RankedLine {
+        line: line.to_string(),
+        value: rank_value(&tl, ranking.by),
+        tl,
+      }
+    })
+    .filter(|r| r.tl.total_chars > 0)
+    .collect();
+
+  res.sort_by(|a, b| {
+    let order = if ranking.ascending {
+      a.value.total_cmp(&b.value)
+    } else {
+      b.value.total_cmp(&a.value)
+    };
+    order.then_with(|| a.line.cmp(&b.line))
+  });
+  res.truncate(ranking.count);
   res
 }
 
-fn move_dist(start: &layout::Pos, end: &layout::Pos) -> f32 {
+pub fn write_ranking_csv(ranked: &[RankedLine], path: &str) -> csv::Result<()> {
+  let mut writer = csv::Writer::from_path(path)?;
+  writer.write_record([
+    "rank",
+    "line",
+    "value",
+    "chars",
+    "dist_u",
+    "dist_mm",
+    "time_ms",
+    "dist_per_char_u",
+    "same_finger_bigrams",
+    "alternating_percent",
+    "effort_per_triad",
+  ])?;
+
+  for (i, r) in ranked.iter().enumerate() {
+    writer.write_record(&[
+      (i + 1).to_string(),
+      r.line.clone(),
+      r.value.to_string(),
+      r.tl.total_chars.to_string(),
+      r.tl.total_dist.to_string(),
+      r.tl.total_dist_mm().to_string(),
+      r.tl.total_time.to_string(),
+      r.tl.u_per_char().to_string(),
+      same_finger_bigrams(&r.tl).to_string(),
+      r.tl.alternating_percent().to_string(),
+      r.tl.effort.per_triad().to_string(),
+    ])?;
+  }
+
+  writer.flush()?;
+  Ok(())
+}
+
+pub fn move_dist(start: &layout::Pos, end: &layout::Pos) -> f32 {
   let x_diff = start.x - end.x;
 
   let y_diff = start.y - end.y;
@@ -529,16 +1518,20 @@ fn move_dist(start: &layout::Pos, end: &layout::Pos) -> f32 {
   (x_diff.powi(2) + y_diff.powi(2)).sqrt()
 }
 
-fn move_time(start: &layout::Pos, end: &layout::Pos) -> i32 {
-  (move_dist(start, end) * MOVE_SPEED) as i32
-}
-
 #[cfg(test)]
 mod tests {
   use super::*;
 
   static QWERTY_PATH: &str = "layouts/qwerty.layout";
 
+  fn gen_timeline(string: &str, gen_anim: bool, lay: &layout::Layout) -> Timeline {
+    gen_timeline_model(string, gen_anim, lay, &model::Model::default())
+  }
+
+  fn move_time(start: &layout::Pos, end: &layout::Pos) -> i32 {
+    model::Timing::default().move_time(0, start, end)
+  }
+
   // Turn a timeline into a flat list of Vec<Keyframes> for testing
   // Multiple Keyframes at the same time are put into the same inner Vec<>
   // Not very efficient, but for testing it's fine
@@ -546,15 +1539,15 @@ mod tests {
     let mut earliest_time = i32::MAX;
     let mut earliest_indices = Vec::new();
 
-    let mut finger_frontier = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut finger_frontier = vec![0; tl.fingers.len()];
 
     let mut flattened = Vec::new();
 
-    // Loop through all 10 fingers and find the earliest frames
+    // Loop through all fingers and find the earliest frames
     'outer: loop {
       let mut this_frame = Vec::new();
       let mut frames_left = false;
-      for i in 0..10 {
+      for i in 0..tl.fingers.len() {
         if tl.fingers[i].len() > finger_frontier[i] {
           if tl.fingers[i][finger_frontier[i]].time < earliest_time {
             frames_left = true;
@@ -719,18 +1712,223 @@ mod tests {
   }
 
   #[test]
-  fn distance_no_shift() {
-    // For now shift movement isn't included in distance
+  fn distance_shift() {
+    // Shift presses count towards distance like any other key
     let mut lay = layout::Layout::default();
     let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
 
     let text = "QHV";
     let tl = gen_timeline(text, true, lay);
 
-    let q_dist = 2.0 * (0.25_f32.powi(2) + 1.0).sqrt();
+    // The left pinky goes straight from q to lshift without going home
+    let q_dist = (0.25_f32.powi(2) + 1.0).sqrt();
     let h_dist = 2.0;
     let v_dist = 2.0 * (0.5_f32.powi(2) + 1.0).sqrt();
-    assert_eq!(tl.total_dist, q_dist + h_dist + v_dist);
+    let rshift_dist = 2.0 * (1.5_f32.powi(2) + 1.0).sqrt();
+    let lshift_dist = (1.5_f32.powi(2) + 2.0_f32.powi(2)).sqrt() + (1.75_f32.powi(2) + 1.0).sqrt();
+    let expected = q_dist + h_dist + v_dist + 2.0 * rshift_dist + lshift_dist;
+    assert!((tl.total_dist - expected).abs() < 0.0001);
+  }
+
+  #[test]
+  fn finger_stats() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+
+    // The pinky only goes home after the last q
+    let q_dist = (0.25_f32.powi(2) + 1.0).sqrt();
+    let tl = gen_timeline("qqq", false, lay);
+    assert_eq!(tl.finger_dist[0], 2.0 * q_dist);
+    assert_eq!(tl.total_dist, 2.0 * q_dist);
+
+    let tl = gen_timeline("dedc", true, lay);
+    let e_dist = (0.25_f32.powi(2) + 1.0).sqrt();
+    let c_dist = (0.5_f32.powi(2) + 1.0).sqrt();
+    assert_eq!(tl.finger_dist[2], 2.0 * e_dist + 2.0 * c_dist);
+    assert_eq!(tl.finger_press_time[2], 4 * PRESS_DUR);
+    assert_eq!(
+      tl.finger_move_time[2],
+      2 * (e_dist * MOVE_SPEED) as i32 + 2 * (c_dist * MOVE_SPEED) as i32
+    );
+    assert_eq!(tl.finger_reach[2], c_dist);
+    assert_eq!(tl.finger_dist[0], 0.0);
+
+    let table = finger_table(&tl);
+    assert_eq!(table.lines().count(), 11);
+    assert!(table.lines().nth(3).unwrap().starts_with("2       Left   Middle         4"));
+  }
+
+  #[test]
+  fn event_stream() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+    let model = model::Model::default();
+
+    let evs: Vec<event::Event> = events("Hi a", lay, &model).collect();
+    assert!(evs.windows(2).all(|w| w[0].time <= w[1].time));
+
+    let downs: Vec<&event::Event> = evs.iter().filter(|e| e.kind == event::EventKind::KeyDown).collect();
+    let keys: Vec<&str> = downs.iter().map(|e| e.key.as_str()).collect();
+    assert_eq!(keys, vec!["lshift", "h", "i", "space", "a"]);
+    assert_eq!(downs[0].ch, None);
+    assert_eq!(downs[1].ch, Some('H'));
+    assert_eq!(downs[1].finger, 6);
+
+    // Each press is a down and an up, and each move a start and an end
+    let count = |kind| evs.iter().filter(|e| e.kind == kind).count();
+    assert_eq!(count(event::EventKind::KeyUp), 5);
+    assert_eq!(count(event::EventKind::MoveStart), count(event::EventKind::MoveEnd));
+
+    // The last thing is a finger getting back home
+    let last = evs.last().unwrap();
+    assert_eq!((last.kind, last.key.as_str(), last.ch), (event::EventKind::MoveEnd, "k", None));
+
+    // Home keys are pressed without moving
+    let evs: Vec<event::Event> = events("j", lay, &model).collect();
+    assert_eq!(evs.len(), 2);
+    assert_eq!(evs[0].time + PRESS_DUR, evs[1].time);
+  }
+
+  #[test]
+  fn replay_log() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+    let model = model::Model::default();
+
+    let presses = keylog::read_log("test/session.log", lay).unwrap();
+    let tl = gen_timeline_log(&presses, true, lay, &model);
+    assert_eq!(tl.total_strokes, 7);
+    assert_eq!(tl.total_presses(), 8);
+    assert_eq!(tl.total_chars, 7);
+    assert_eq!(tl.total_words, 2);
+    // Every key goes down when the log says, and playback needs
+    // keyframes in order
+    assert!(tl.fingers.iter().all(|f| f.windows(2).all(|w| w[0].time <= w[1].time)));
+    let h = tl.fingers[6].iter().find(|kf| kf.start_press).unwrap();
+    assert_eq!(h.time, 120);
+    // Shift and a
+    assert_eq!(tl.finger_press_time[0], 180 + 60);
+
+    // Presses far enough apart cost the same as simulated ones
+    let press = |name, down| keylog::KeyPress {
+      key: keylog::find_key(lay, name).unwrap(),
+      down,
+      up: down + PRESS_DUR,
+      ch: name.chars().next(),
+      modifier: false,
+    };
+    let tl = gen_timeline_log(&[press("e", 0), press("j", 1000), press("e", 2000)], false, lay, &model);
+    assert_eq!(tl.total_dist, gen_timeline("eje", false, lay).total_dist);
+    assert_eq!(tl.total_time, 2000 + PRESS_DUR + move_time(&lay.homes[2].pos, &lay.char_keys[&'e'].key.pos));
+  }
+
+  #[test]
+  fn critical_path() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+    let cause_of_last = |text: &str| {
+      let cp = gen_timeline(text, false, lay).critical;
+      let prev = gen_timeline(&text[..text.len() - 1], false, lay).critical;
+      let cause = CAUSES.iter().position(|c| cp.counts[*c as usize] > prev.counts[*c as usize]).unwrap();
+      (CAUSES[cause], cp.worst_finger(CAUSES[cause]).unwrap())
+    };
+
+    // Home keys only wait for the last press and the gap
+    let cp = gen_timeline("fj", false, lay).critical;
+    assert_eq!(cp.counts, [2, 0, 0, 0]);
+    assert_eq!(cp.time[Cause::PressGap as usize], PRESS_DUR + 2 * PRESS_GAP);
+    assert_eq!(cp.percent(Cause::PressGap), 100.0);
+
+    assert_eq!(cause_of_last("jj"), (Cause::PressGap, 6));
+    // The middle finger has to let go of d before it can go to e
+    assert_eq!(cause_of_last("de"), (Cause::Travel, 2));
+    // The hand waits for f before the middle finger can move to e
+    assert_eq!(cause_of_last("fe"), (Cause::SameHand, 2));
+    // Shift is further away than k
+    assert_eq!(cause_of_last("jK"), (Cause::Modifier, 0));
+
+    let tl = gen_timeline("The quick brown fox", false, lay);
+    assert_eq!(tl.critical.counts.iter().sum::<u32>(), tl.total_strokes);
+    assert!(critical_table(&tl).lines().nth(3).unwrap().starts_with("Travel"));
+  }
+
+  #[test]
+  fn ranking() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+    let model = model::Model::default();
+    let text = "fj\njf\nded\nqz\na\nminimum";
+    let lines = |ranking: &Ranking| -> Vec<String> {
+      compare_lines(text, lay, &model, ranking).into_iter().map(|r| r.line).collect()
+    };
+
+    let by_dist = lines(&Ranking::default());
+    assert_eq!(by_dist[0], "minimum");
+    // No distance at all, so alphabetical
+    assert_eq!(by_dist[3..], ["a", "fj", "jf"]);
+
+    let shortest = Ranking {
+      count: 2,
+      ascending: true,
+      min_len: 2,
+      max_len: Some(3),
+      ..Default::default()
+    };
+    assert_eq!(lines(&shortest), vec!["fj", "jf"]);
+
+    let sfb = Ranking {
+      by: RankBy::SameFinger,
+      count: 1,
+      ..Default::default()
+    };
+    let top = compare_lines(text, lay, &model, &sfb);
+    assert_eq!((top[0].line.as_str(), top[0].value), ("ded", 2.0));
+
+    // One char lines have nothing to alternate
+    let alt = Ranking {
+      by: RankBy::Alternation,
+      ..Default::default()
+    };
+    assert_eq!(lines(&alt)[0], "fj");
+    assert_eq!(parse_rank_by("SFB"), Some(RankBy::SameFinger));
+
+    // Blank and untypeable lines aren't ranked, even when they'd cost
+    // nothing
+    let text = "\nfj\n  \nded\n\u{e9}\n";
+    for by in [RankBy::DistPerChar, RankBy::Effort].iter() {
+      let asc = Ranking {
+        by: *by,
+        ascending: true,
+        ..Default::default()
+      };
+      let ranked = compare_lines(text, lay, &model, &asc);
+      let names: Vec<&str> = ranked.iter().map(|r| r.line.as_str()).collect();
+      assert_eq!(names, vec!["fj", "ded"]);
+      assert!(ranked.iter().all(|r| !r.value.is_nan()));
+    }
+  }
+
+  #[test]
+  fn rows_and_columns() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+
+    let tl = gen_timeline("aqz1 gh", false, lay);
+    assert_eq!(tl.row_counts, [1, 1, 3, 1, 1]);
+    assert_eq!(tl.home_row_percent(), 300.0 / 7.0);
+    assert_eq!(tl.stretch_presses, 2);
+    assert_eq!(tl.hand_percent(layout::Hand::Left), 500.0 / 6.0);
+    assert_eq!(tl.hand_percent(layout::Hand::Right), 100.0 / 6.0);
+
+    // Stagger doesn't move the index finger's own column
+    let tl = gen_timeline("45vbmn", false, lay);
+    assert_eq!(tl.stretch_presses, 3);
+    assert_eq!(tl.stretch_percent(), 50.0);
+
+    // Shift is a stretch for the pinky
+    let tl = gen_timeline("P", false, lay);
+    assert_eq!(tl.row_counts[Row::Bottom as usize], 1);
+    assert_eq!(tl.stretch_presses, 1);
   }
 
   #[test]
@@ -748,18 +1946,91 @@ mod tests {
   }
 
   #[test]
-  fn usage_no_shift() {
-    // For now shifting doesn't get counted as usage
+  fn usage_shift() {
+    // Shift presses count towards usage
     let mut lay = layout::Layout::default();
     let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
 
     let text = "QPWO";
     let tl = gen_timeline(text, true, lay);
 
-    assert_eq!(tl.usage_percent(0), 25);
-    assert_eq!(tl.usage_percent(1), 25);
-    assert_eq!(tl.usage_percent(8), 25);
-    assert_eq!(tl.usage_percent(9), 25);
+    assert_eq!(tl.total_presses(), 8);
+    assert_eq!(tl.finger_counts[0], 3);
+    assert_eq!(tl.finger_counts[9], 3);
+    assert_eq!(tl.usage_percent(0), 37);
+    assert_eq!(tl.usage_percent(1), 12);
+    assert_eq!(tl.usage_percent(8), 12);
+  }
+
+  #[test]
+  fn shift_policies() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+
+    let with_shift = |shift: model::ShiftModel| {
+      let model = model::Model {
+        shift,
+        ..Default::default()
+      };
+      gen_timeline_model("Hi NASA", false, lay, &model)
+    };
+
+    let opposite = with_shift(Default::default());
+    assert_eq!(opposite.total_strokes, 7);
+    // H and the right hand's A use lshift, S and the left hand A rshift
+    assert_eq!(opposite.finger_counts[0], 1 + 1 + 2);
+
+    let left = with_shift(model::ShiftModel {
+      policy: model::ShiftPolicy::Left,
+      ..Default::default()
+    });
+    // The pinky holding shift can't also press a
+    assert_eq!(left.finger_counts[0], 5);
+    assert!(left.total_time > opposite.total_time);
+
+    // Tapping shift is a stroke of its own
+    let sticky = with_shift(model::ShiftModel {
+      policy: model::ShiftPolicy::Sticky,
+      ..Default::default()
+    });
+    assert_eq!(sticky.total_strokes, 7 + 5);
+    assert_eq!(sticky.total_presses(), opposite.total_presses());
+
+    // Caps lock is pressed before and after the run
+    let caps = with_shift(model::ShiftModel {
+      caps_lock_min: Some(3),
+      ..Default::default()
+    });
+    assert_eq!(caps.total_strokes, 7 + 2);
+    assert_eq!(caps.total_presses(), 7 + 1 + 2);
+
+    // Caps Word is turned on with both shifts, and off by the word ending
+    let word = with_shift(model::ShiftModel {
+      caps_word_min: Some(3),
+      ..Default::default()
+    });
+    assert_eq!(word.total_strokes, 7 + 1);
+    assert_eq!(word.total_presses(), 7 + 1 + 2);
+  }
+
+  #[test]
+  fn caps_lock_runs() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty-intl.layout").unwrap();
+    let model = model::Model {
+      shift: model::ShiftModel {
+        caps_lock_min: Some(3),
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+
+    // Caps lock goes on for the run and off again
+    let tl = gen_timeline_model("ABCD x", false, lay, &model);
+    assert_eq!(tl.total_strokes, 6 + 2);
+    // The dead key capital ends the run, so AB is too short for caps lock
+    let tl = gen_timeline_model("ABÉD x", false, lay, &model);
+    assert_eq!(tl.total_strokes, 6 + 1);
   }
 
   #[test]
@@ -794,6 +2065,160 @@ mod tests {
     assert_eq!(tl.total_chars, tl_no_anim.total_chars);
   }
 
+  #[test]
+  fn dead_keys() {
+    // Each char of a sequence costs every press in it
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty-intl.layout").unwrap();
+
+    let text = "é";
+    let tl = gen_timeline(text, true, lay);
+
+    let quote_dist = 2.0;
+    let e_dist = 2.0 * (0.25_f32.powi(2) + 1.0).sqrt();
+    assert_eq!(tl.total_strokes, 2);
+    assert_eq!(tl.total_dist, quote_dist + e_dist);
+    assert_eq!(tl.finger_counts[9], 1);
+    assert_eq!(tl.finger_counts[2], 1);
+
+    // A dead key's own char needs a space after it
+    let tl = gen_timeline("'", false, lay);
+    assert_eq!(tl.total_strokes, 2);
+    assert_eq!(tl.total_dist, quote_dist);
+  }
+
+  #[test]
+  fn unmapped() {
+    // Chars the layout can't type aren't counted as typed
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+
+    let text = "née ø";
+    let tl = gen_timeline(text, true, lay);
+    common_invariants(&tl, "ne ");
+
+    assert_eq!(tl.total_chars, 3);
+    assert_eq!(tl.total_unmapped, 2);
+    assert_eq!(tl.total_strokes, 3);
+    assert_eq!(tl.coverage_percent(), 60.0);
+  }
+
+  #[test]
+  fn named_keys() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+
+    // Newlines press enter, <BS> presses backspace
+    let text = "a\nb<BS>";
+    let tl = gen_timeline(text, true, lay);
+
+    let enter_dist = 2.0 * 2.0;
+    let b_dist = 2.0 * (1.5_f32.powi(2) + 1.0).sqrt();
+    let bs_dist = 2.0 * (2.25_f32.powi(2) + 2.0_f32.powi(2)).sqrt();
+    assert_eq!(tl.total_chars, 4);
+    assert_eq!(tl.total_strokes, 4);
+    assert_eq!(tl.total_dist, enter_dist + b_dist + bs_dist);
+    assert_eq!(tl.finger_counts[9], 2);
+  }
+
+  #[test]
+  fn thumbs() {
+    // Both thumbs are used and counted, and as neither hand every
+    // press around them counts as alternating
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "test/thumbs.layout").unwrap();
+
+    let text = "a b\nc";
+    let tl = gen_timeline(text, true, lay);
+    common_invariants(&tl, "a bc");
+
+    assert_eq!(tl.finger_counts.len(), 4);
+    assert_eq!(tl.finger_counts, vec![2, 1, 1, 1]);
+    assert_eq!(tl.thumb_percent(), 40);
+    assert_eq!(tl.total_switches, 5);
+  }
+
+  #[test]
+  fn errors() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, QWERTY_PATH).unwrap();
+
+    let text = "The Quick Brown Fox Jumps Over The Lazy Dog.";
+    let perfect = gen_timeline(text, false, lay);
+
+    // A rate of 0 changes nothing
+    let model = model::Model {
+      errors: Some(model::ErrorModel::new(0.0, 1)),
+      ..Default::default()
+    };
+    let tl = gen_timeline_model(text, false, lay, &model);
+    assert_eq!(tl.total_errors(), 0);
+    assert_eq!(tl.total_time, perfect.total_time);
+
+    // Every mistake costs a wrong press and a backspace
+    let model = model::Model {
+      errors: Some(model::ErrorModel::new(0.2, 1)),
+      ..Default::default()
+    };
+    let tl = gen_timeline_model(text, true, lay, &model);
+    assert!(tl.total_errors() > 0);
+    assert_eq!(tl.total_strokes, perfect.total_strokes + 2 * tl.total_errors());
+    assert_eq!(tl.total_chars, perfect.total_chars);
+    assert!(tl.total_time > perfect.total_time);
+
+    // Same seed, same mistakes
+    let again = gen_timeline_model(text, false, lay, &model);
+    assert_eq!(again.total_time, tl.total_time);
+    assert_eq!(again.finger_errors, tl.finger_errors);
+  }
+
+  #[test]
+  fn chords() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/chorded.layout").unwrap();
+
+    let text = "it is the end of that";
+    let tl = gen_timeline(text, false, lay);
+    assert_eq!(tl.total_chars, text.len() as u32);
+    assert_eq!(tl.coverage_percent(), 100.0);
+    // Every word but "end" is a single chord, and spaces take a stroke
+    assert_eq!(tl.total_strokes, 5 + 3 + 5);
+    assert_eq!(tl.strokes_per_word(), 13.0 / 6.0);
+    assert_eq!(tl.total_dist, 0.0);
+    assert_eq!(gen_timeline("", false, lay).strokes_per_word(), 0.0);
+  }
+
+  #[test]
+  fn mobile() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/mobile-qwerty.layout").unwrap();
+    let model = model::Model {
+      mobile: Some(model::MobileModel { key_pitch: 6.0 }),
+      ..Default::default()
+    };
+
+    // Thumbs stay on s instead of going back to d between presses
+    let tl = gen_timeline_model("sss", false, lay, &model);
+    assert_eq!(tl.total_dist, 0.75);
+    assert_eq!(tl.total_dist_mm(), 4.5);
+    assert_eq!(gen_timeline("sss", false, lay).total_dist, 2.0 * 0.75);
+
+    let de = move_dist(&layout::Pos { x: 1.875, y: 1.0 }, &layout::Pos { x: 1.5, y: 0.0 });
+    let tl = gen_timeline_model("ded", true, lay, &model);
+    assert!((tl.total_dist - 2.0 * de).abs() < 0.0001);
+
+    // Swipes go through key centers, ignoring case and punctuation
+    let ea = move_dist(&layout::Pos { x: 1.5, y: 0.0 }, &layout::Pos { x: 0.375, y: 1.0 });
+    let dead = swipe_len("Dead,", lay).unwrap();
+    assert!((dead - (de + ea + 1.5)).abs() < 0.0001);
+    assert_eq!(swipe_len("-", lay), None);
+
+    let tl = gen_timeline_model("Dead, dd - ded", false, lay, &model);
+    assert_eq!(tl.swipe_words, 3);
+    assert!((tl.swipe_per_word() - (dead + 2.0 * de) / 3.0).abs() < 0.0001);
+    assert_eq!(gen_timeline("dead", false, lay).swipe_words, 0);
+  }
+
   #[test]
   #[ignore = "Parallel timelines have slight errors, stitching fix not yet implemented"]
   fn parallel() {
@@ -804,7 +2229,7 @@ mod tests {
 
     let text = "The Quick Brown\nFox Jumps Over\nThe Lazy Dog.";
     let tl = gen_timeline(text, true, lay);
-    let tl_parallel = gen_timeline_parallel(text, lay);
+    let tl_parallel = gen_timeline_parallel(text, lay, &model::Model::default());
 
     assert_eq!(tl.total_time, tl_parallel.total_time);
     assert_eq!(tl.total_dist, tl_parallel.total_dist);
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use super::analyze;
+use super::event;
+use super::layout;
+use super::model;
+
+// Pixels per cell of the heatmap
+static CELL: usize = 16;
+
+// Two chars typed one after the other somewhere in a text
+pub struct Bigram {
+  pub first: char,
+  pub second: char,
+  pub count: u32,
+  pub time: i32, // ms from the last press of first to the first press of second
+  pub dist: f32, // u the fingers move to type second after first, including going back home
+}
+
+// When each press for a char of text goes down
+fn downs(text: &str, lay: &layout::Layout, model: &model::Model) -> Vec<i32> {
+  analyze::events(text, lay, model)
+    .filter(|e| e.kind == event::EventKind::KeyDown && e.ch.is_some())
+    .map(|e| e.time)
+    .collect()
+}
+
+// Every pair of chars the layout can type that occurs in text, with
+// the time and distance simulating just the two of them takes.
+// Sorted by the first char, then the second
+pub fn bigrams(text: &str, lay: &layout::Layout, model: &model::Model) -> Vec<Bigram> {
+  // Errors would make the same bigram cost different amounts
+  let model = model::Model {
+    errors: None,
+    ..model.clone()
+  };
+
+  let mut counts: HashMap<(char, char), u32> = HashMap::new();
+  let chars: Vec<char> = text.chars().collect();
+  for pair in chars.windows(2) {
+    if pair.iter().all(|c| lay.char_keys.contains_key(c)) {
+      *counts.entry((pair[0], pair[1])).or_insert(0) += 1;
+    }
+  }
+
+  // The presses and distance of each first char on its own
+  let mut singles: HashMap<char, (usize, f32)> = HashMap::new();
+  let mut res: Vec<Bigram> = Vec::new();
+  for ((first, second), count) in counts {
+    let (presses, single_dist) = *singles.entry(first).or_insert_with(|| {
+      let s = first.to_string();
+      (downs(&s, lay, &model).len(), analyze::gen_timeline_model(&s, false, lay, &model).total_dist)
+    });
+
+    let pair: String = [first, second].iter().collect();
+    let pair_downs = downs(&pair, lay, &model);
+    let time = match (presses.checked_sub(1).and_then(|i| pair_downs.get(i)), pair_downs.get(presses)) {
+      (Some(a), Some(b)) => b - a,
+      _ => continue,
+    };
+    let dist = analyze::gen_timeline_model(&pair, false, lay, &model).total_dist - single_dist;
+
+    res.push(Bigram {
+      first,
+      second,
+      count,
+      time,
+      dist,
+    });
+  }
+
+  res.sort_by_key(|b| (b.first, b.second));
+  res
+}
+
+// How a char is shown in the heatmap
+fn label(c: char) -> String {
+  match c {
+    ' ' => "␣".to_string(),
+    '\n' => "⏎".to_string(),
+    '\t' => "⇥".to_string(),
+    c => c.to_string(),
+  }
+}
+
+fn json_str(s: &str) -> String {
+  let mut res = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"' => res.push_str("\\\""),
+      '\\' => res.push_str("\\\\"),
+      '\n' => res.push_str("\\n"),
+      '\t' => res.push_str("\\t"),
+      c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+      c => res.push(c),
+    }
+  }
+  res.push('"');
+  res
+}
+
+fn xml_escape(s: &str) -> String {
+  s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Every char in a bigram, sorted
+fn axis(bigrams: &[Bigram]) -> Vec<char> {
+  let mut chars: Vec<char> = bigrams.iter().flat_map(|b| [b.first, b.second]).collect();
+  chars.sort_unstable();
+  chars.dedup();
+  chars
+}
+
+// The bigrams as matrices indexed by first char, then second, null
+// where a pair doesn't occur
+pub fn to_json(bigrams: &[Bigram]) -> String {
+  let chars = axis(bigrams);
+  let find = |a: char, b: char| bigrams.iter().find(|bg| bg.first == a && bg.second == b);
+  let matrix = |value: &dyn Fn(&Bigram) -> String| {
+    let rows: Vec<String> = chars
+      .iter()
+      .map(|a| {
+        let cells: Vec<String> = chars.iter().map(|b| find(*a, *b).map_or("null".to_string(), value)).collect();
+        format!("[{}]", cells.join(", "))
+      })
+      .collect();
+    format!("[\n    {}\n  ]", rows.join(",\n    "))
+  };
+
+  let names: Vec<String> = chars.iter().map(|c| json_str(&c.to_string())).collect();
+  format!(
+    "{{\n  \"chars\": [{}],\n  \"count\": {},\n  \"time_ms\": {},\n  \"dist_u\": {}\n}}\n",
+    names.join(", "),
+    matrix(&|b| b.count.to_string()),
+    matrix(&|b| b.time.to_string()),
+    matrix(&|b| b.dist.to_string())
+  )
+}
+
+// Heatmap of the time of each bigram, white for the fastest and red
+// for the slowest, with the first char down the side. Hovering over a
+// cell shows its numbers
+pub fn to_svg(bigrams: &[Bigram], title: &str) -> String {
+  let chars = axis(bigrams);
+  let min = bigrams.iter().map(|b| b.time).min().unwrap_or(0);
+  let max = bigrams.iter().map(|b| b.time).max().unwrap_or(0);
+  let side = CELL * (chars.len() + 2);
+
+  let mut svg = format!(
+    "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"{}\">\n",
+    side,
+    side + CELL,
+    CELL - 4
+  );
+  svg.push_str(&format!("<text x=\"0\" y=\"{}\">{}</text>\n", CELL - 4, xml_escape(title)));
+
+  let middle = |i: usize| CELL * (i + 2) + CELL / 2;
+  for (i, c) in chars.iter().enumerate() {
+    let text = xml_escape(&label(*c));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n", middle(i), CELL * 2 - 4, text));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n", CELL / 2 + CELL, middle(i) + 4, text));
+  }
+
+  for b in bigrams {
+    let x = CELL * (chars.binary_search(&b.second).unwrap() + 2);
+    let y = CELL * (chars.binary_search(&b.first).unwrap() + 2);
+    let heat = if max > min { (b.time - min) as f32 / (max - min) as f32 } else { 0.0 };
+    let shade = (255.0 * (1.0 - heat)).round() as u8;
+    svg.push_str(&format!(
+      "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"rgb(255,{},{})\"><title>{}: {}ms, {:.2}u, {} times</title></rect>\n",
+      x,
+      y,
+      CELL,
+      CELL,
+      shade,
+      shade,
+      xml_escape(&format!("{}{}", label(b.first), label(b.second))),
+      b.time,
+      b.dist,
+      b.count
+    ));
+  }
+
+  svg.push_str("</svg>\n");
+  svg
+}
+
+// Write the bigrams to path as CSV, JSON or an SVG heatmap, depending
+// on its extension. Other extensions are an error
+pub fn write(bigrams: &[Bigram], path: &str, title: &str) -> Result<(), String> {
+  let contents = if path.ends_with(".csv") {
+    return write_csv(bigrams, path).map_err(|e| e.to_string());
+  } else if path.ends_with(".json") {
+    to_json(bigrams)
+  } else if path.ends_with(".svg") {
+    to_svg(bigrams, title)
+  } else {
+    return Err(format!("{} doesn't end in .csv, .json or .svg", path));
+  };
+  std::fs::write(path, contents).map_err(|e| e.to_string())
+}
+
+fn write_csv(bigrams: &[Bigram], path: &str) -> csv::Result<()> {
+  let mut writer = csv::Writer::from_path(path)?;
+  writer.write_record(["first", "second", "count", "time_ms", "dist_u"])?;
+  for b in bigrams {
+    writer.write_record(&[
+      b.first.to_string(),
+      b.second.to_string(),
+      b.count.to_string(),
+      b.time.to_string(),
+      b.dist.to_string(),
+    ])?;
+  }
+
+  writer.flush()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn qwerty() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let timing = model::Timing::default();
+
+    let bgs = bigrams("dedj dj~", lay, &model::Model::default());
+    let pairs: Vec<String> = bgs.iter().map(|b| [b.first, b.second].iter().collect()).collect();
+    assert_eq!(pairs, vec![" d", "de", "dj", "ed", "j ", "j~"]);
+
+    let dj = &bgs[2];
+    assert_eq!(dj.count, 2);
+    // Home keys on different hands only wait for the last press
+    assert_eq!(dj.time, timing.press_dur + timing.press_gap);
+    assert_eq!(dj.dist, 0.0);
+
+    // Same finger, so it has to go up and come back
+    let de = &bgs[1];
+    let e_dist = analyze::move_dist(&lay.homes[2].pos, &lay.char_keys[&'e'].key.pos);
+    assert_eq!(de.dist, 2.0 * e_dist);
+    assert!(de.time > dj.time);
+
+    // ~ takes shift, which is held before it goes down
+    assert!(bgs[5].time > dj.time);
+  }
+
+  #[test]
+  fn formats() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let bgs = bigrams("a\"a", lay, &model::Model::default());
+
+    let json = to_json(&bgs);
+    assert!(json.contains("\"chars\": [\"\\\"\", \"a\"]"));
+    assert!(json.contains("\"count\": [\n    [null, 1],\n    [1, null]\n  ]"));
+
+    let svg = to_svg(&bgs, "a & b");
+    assert!(svg.contains("a &amp; b"));
+    assert_eq!(svg.matches("<rect").count(), 2);
+    assert!(svg.contains("&quot;a:"));
+
+    assert_eq!(write(&bgs, "bigrams.png", "").unwrap_err(), "bigrams.png doesn't end in .csv, .json or .svg");
+  }
+}
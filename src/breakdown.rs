@@ -0,0 +1,234 @@
+use super::analyze;
+use super::layout;
+use super::model;
+
+// Costliest words listed, and words shown either side of each
+static TOP_WORDS: usize = 10;
+static CONTEXT_WORDS: usize = 3;
+// Longer sentences are cut short when printed
+static SENTENCE_WIDTH: usize = 60;
+
+// A word or sentence of a text and what typing it cost
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+  pub start: usize, // Byte offsets in the text
+  pub end: usize,
+  pub chars: u32, // # of chars typed
+  pub time: i32,  // ms
+  pub dist: f32,  // u
+  pub same_finger: u32,
+}
+
+impl Span {
+  pub fn text<'t>(&self, text: &'t str) -> &'t str {
+    &text[self.start..self.end]
+  }
+
+  pub fn ms_per_char(&self) -> f32 {
+    if self.chars == 0 {
+      return 0.0;
+    }
+    self.time as f32 / self.chars as f32
+  }
+}
+
+// Each word and sentence of a text, in order
+pub struct Breakdown {
+  pub words: Vec<Span>,
+  pub sentences: Vec<Span>,
+}
+
+// Byte ranges of the runs of text without whitespace
+fn word_ranges(text: &str) -> Vec<(usize, usize)> {
+  let mut res = Vec::new();
+  let mut start = None;
+  for (i, c) in text.char_indices() {
+    match (c.is_whitespace(), start) {
+      (false, None) => start = Some(i),
+      (true, Some(s)) => {
+        res.push((s, i));
+        start = None;
+      }
+      _ => {}
+    }
+  }
+  if let Some(s) = start {
+    res.push((s, text.len()));
+  }
+  res
+}
+
+// Byte ranges of the sentences, which end with a line or a . ! or ?
+// before whitespace. Whitespace between sentences is left out
+fn sentence_ranges(text: &str) -> Vec<(usize, usize)> {
+  let mut res = Vec::new();
+  let mut start = None;
+  let mut chars = text.char_indices().peekable();
+  while let Some((i, c)) = chars.next() {
+    if start.is_none() && !c.is_whitespace() {
+      start = Some(i);
+    }
+    let s = match start {
+      Some(s) => s,
+      None => continue,
+    };
+
+    let next_blank = chars.peek().is_none_or(|(_, n)| n.is_whitespace());
+    if c == '\n' {
+      res.push((s, i));
+      start = None;
+    } else if matches!(c, '.' | '!' | '?') && next_blank {
+      res.push((s, i + c.len_utf8()));
+      start = None;
+    }
+  }
+  if let Some(s) = start {
+    res.push((s, text.trim_end().len().max(s)));
+  }
+  res
+}
+
+// Adds up the costs of the strokes starting in each range. Both are in
+// order, so they're walked together
+fn spans(ranges: &[(usize, usize)], costs: &[analyze::StrokeCost]) -> Vec<Span> {
+  let mut costs = costs.iter().peekable();
+  ranges
+    .iter()
+    .map(|(start, end)| {
+      let mut span = Span {
+        start: *start,
+        end: *end,
+        chars: 0,
+        time: 0,
+        dist: 0.0,
+        same_finger: 0,
+      };
+      while let Some(c) = costs.next_if(|c| c.idx < *end) {
+        if c.idx >= *start {
+          span.chars += c.chars;
+          span.time += c.time;
+          span.dist += c.dist;
+          span.same_finger += c.same_finger;
+        }
+      }
+      span
+    })
+    .collect()
+}
+
+// The time, distance and same finger bigrams of each word and sentence
+// of text. A stroke's cost goes to the word it starts in, so spaces
+// only count towards the sentence around them, if there is one
+pub fn breakdown(text: &str, lay: &layout::Layout, model: &model::Model) -> Breakdown {
+  let costs = analyze::stroke_costs(text, lay, model);
+  Breakdown {
+    words: spans(&word_ranges(text), &costs),
+    sentences: spans(&sentence_ranges(text), &costs),
+  }
+}
+
+// Indices of the words, slowest first
+pub fn costliest(words: &[Span]) -> Vec<usize> {
+  let mut idxs: Vec<usize> = (0..words.len()).collect();
+  idxs.sort_by(|a, b| words[*b].time.cmp(&words[*a].time).then(a.cmp(b)));
+  idxs
+}
+
+// Word i of words with the ones around it, and it in brackets
+fn in_context(text: &str, words: &[Span], i: usize) -> String {
+  let first = i.saturating_sub(CONTEXT_WORDS);
+  let last = (i + CONTEXT_WORDS).min(words.len() - 1);
+  let mut res = String::new();
+  if first > 0 {
+    res.push_str("... ");
+  }
+  let shown: Vec<String> = (first..=last)
+    .map(|j| match j == i {
+      true => format!("[{}]", words[j].text(text)),
+      false => words[j].text(text).to_string(),
+    })
+    .collect();
+  res.push_str(&shown.join(" "));
+  if last + 1 < words.len() {
+    res.push_str(" ...");
+  }
+  res
+}
+
+fn shorten(s: &str) -> String {
+  let s = s.replace('\n', " ");
+  match s.char_indices().nth(SENTENCE_WIDTH) {
+    Some((i, _)) => format!("{}...", &s[..i]),
+    None => s,
+  }
+}
+
+pub fn print_breakdown(text: &str, b: &Breakdown) {
+  println!("\nCostliest words:");
+  for i in costliest(&b.words).into_iter().take(TOP_WORDS) {
+    let w = &b.words[i];
+    println!(
+      "  {:>6}ms {:>6.2}u {:>2} sfb  {}",
+      w.time,
+      w.dist,
+      w.same_finger,
+      in_context(text, &b.words, i)
+    );
+  }
+
+  println!("Sentences:");
+  for (i, s) in b.sentences.iter().enumerate() {
+    println!(
+      "{:3}: {}ms ({:.1}ms per char), {:.2}u, {} sfb: {}",
+      i + 1,
+      s.time,
+      s.ms_per_char(),
+      s.dist,
+      s.same_finger,
+      shorten(s.text(text))
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ranges() {
+    let text = "Hi there.  How are\nyou? 3.5 fine!\n  ok";
+    let words: Vec<&str> = word_ranges(text).iter().map(|(s, e)| &text[*s..*e]).collect();
+    assert_eq!(words, vec!["Hi", "there.", "How", "are", "you?", "3.5", "fine!", "ok"]);
+    let sentences: Vec<&str> = sentence_ranges(text).iter().map(|(s, e)| &text[*s..*e]).collect();
+    assert_eq!(sentences, vec!["Hi there.", "How are", "you?", "3.5 fine!", "ok"]);
+  }
+
+  #[test]
+  fn words() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let model = model::Model::default();
+
+    let text = "fj dedede. fj";
+    let b = breakdown(text, lay, &model);
+    let costs = analyze::stroke_costs(text, lay, &model);
+    assert_eq!(costs.len(), text.len());
+
+    let names: Vec<&str> = b.words.iter().map(|w| w.text(text)).collect();
+    assert_eq!(names, vec!["fj", "dedede.", "fj"]);
+    assert_eq!(b.sentences.len(), 2);
+    // Spaces don't count towards words, and the one between the
+    // sentences doesn't count towards either
+    let total: i32 = costs.iter().map(|c| c.time).sum();
+    let spaces: i32 = costs.iter().filter(|c| text[c.idx..].starts_with(' ')).map(|c| c.time).sum();
+    assert_eq!(b.words.iter().map(|w| w.time).sum::<i32>(), total - spaces);
+    assert_eq!(b.sentences.iter().map(|s| s.time).sum::<i32>(), total - costs[10].time);
+
+    // d and e are on the same finger
+    assert_eq!(b.words[1].same_finger, 5);
+    assert_eq!(b.sentences[0].same_finger, 5);
+    assert_eq!(b.words[0].dist, 0.0);
+    assert_eq!(costliest(&b.words)[0], 1);
+    assert_eq!(in_context(text, &b.words, 1), "fj [dedede.] fj");
+  }
+}
@@ -0,0 +1,234 @@
+use super::analyze;
+use super::event;
+use super::keylog;
+use super::layout;
+use super::model;
+
+// Pauses longer than this (ms) are thinking rather than typing, and
+// split a session into runs
+static MAX_INTERVAL: i32 = 1000;
+
+// Search steps smaller than this don't change the timing enough to
+// matter
+static MIN_STEP: f32 = 0.5;
+static MAX_PASSES: usize = 200;
+
+// Stretch of a session with only chars and modifiers pressed
+struct Run {
+  text: String,
+  downs: Vec<i32>, // ms each char went down
+}
+
+pub struct Fit {
+  pub timing: model::Timing,
+  pub intervals: usize, // # of intervals fitted against
+  pub rms_before: f32,  // ms, with the timing the fit started from
+  pub rms_after: f32,
+}
+
+fn runs(presses: &[keylog::KeyPress]) -> Vec<Run> {
+  let mut res = Vec::new();
+  let mut run = Run {
+    text: String::new(),
+    downs: Vec::new(),
+  };
+
+  for p in presses.iter().filter(|p| !p.modifier) {
+    let pause = run.downs.last().is_some_and(|d| p.down - d > MAX_INTERVAL);
+    if p.ch.is_none() || pause {
+      res.push(run);
+      run = Run {
+        text: String::new(),
+        downs: Vec::new(),
+      };
+    }
+    if let Some(c) = p.ch {
+      run.text.push(c);
+      run.downs.push(p.down);
+    }
+  }
+  res.push(run);
+
+  res.retain(|r| r.downs.len() > 1);
+  res
+}
+
+// When the simulation presses each char of text. None if some chars
+// take more than one press, like dead keys, so they can't be matched
+// up with the session
+fn predict(text: &str, len: usize, lay: &layout::Layout, model: &model::Model) -> Option<Vec<i32>> {
+  let downs: Vec<i32> = analyze::events(text, lay, model)
+    .filter(|e| e.kind == event::EventKind::KeyDown && e.ch.is_some())
+    .map(|e| e.time)
+    .collect();
+  (downs.len() == len).then_some(downs)
+}
+
+// Sum of the squared differences between the session's intervals and
+// the simulated ones
+fn error(runs: &[Run], lay: &layout::Layout, model: &model::Model) -> f32 {
+  let mut sum = 0.0;
+  for run in runs {
+    let predicted = match predict(&run.text, run.downs.len(), lay, model) {
+      Some(p) => p,
+      None => continue,
+    };
+    for i in 1..run.downs.len() {
+      let diff = (predicted[i] - predicted[i - 1]) - (run.downs[i] - run.downs[i - 1]);
+      sum += (diff as f32).powi(2);
+    }
+  }
+  sum
+}
+
+// The gap, same hand wait and finger speeds are params[0], params[1]
+// and params[2..]
+fn with_params(base: &model::Timing, params: &[f32]) -> model::Timing {
+  model::Timing {
+    press_gap: params[0].round() as i32,
+    same_hand: params[1].round() as i32,
+    finger_speed: params[2..].to_vec(),
+    ..base.clone()
+  }
+}
+
+// Fit model's timing to a recorded session by least squares. Keys are
+// held as long as they are on average, and the gap between presses,
+// the wait before a hand moves again and each finger's speed are
+// searched for, one at a time, as the values that make the simulated
+// intervals between chars closest to the recorded ones
+pub fn calibrate(presses: &[keylog::KeyPress], lay: &layout::Layout, model: &model::Model) -> Option<Fit> {
+  let runs: Vec<Run> = runs(presses)
+    .into_iter()
+    .filter(|r| predict(&r.text, r.downs.len(), lay, model).is_some())
+    .collect();
+  let intervals: usize = runs.iter().map(|r| r.downs.len() - 1).sum();
+  if intervals == 0 {
+    println!("No typing to calibrate with");
+    return None;
+  }
+  let rms = |sum: f32| (sum / intervals as f32).sqrt();
+
+  let held: Vec<i32> = presses.iter().filter(|p| !p.modifier).map(|p| p.up - p.down).collect();
+  let mut model = model.clone();
+  model.timing.press_dur = (held.iter().sum::<i32>() as f32 / held.len() as f32).round() as i32;
+  let rms_before = rms(error(&runs, lay, &model));
+
+  let mut params = vec![model.timing.press_gap as f32, model.timing.same_hand as f32];
+  params.extend((0..lay.homes.len()).map(|i| model.timing.speed(i)));
+  let mut steps: Vec<f32> = params.iter().map(|p| (p / 4.0).max(8.0)).collect();
+  let mut best = error(&runs, lay, &model);
+
+  for _ in 0..MAX_PASSES {
+    let mut improved = false;
+    for k in 0..params.len() {
+      for dir in [1.0, -1.0] {
+        let mut cand = params.clone();
+        cand[k] = (params[k] + dir * steps[k]).max(0.0);
+        model.timing = with_params(&model.timing, &cand);
+        let err = error(&runs, lay, &model);
+        if err < best {
+          best = err;
+          params = cand;
+          improved = true;
+          break;
+        }
+      }
+    }
+
+    if !improved {
+      if steps.iter().all(|s| *s < MIN_STEP) {
+        break;
+      }
+      for s in steps.iter_mut() {
+        *s /= 2.0;
+      }
+    }
+  }
+
+  Some(Fit {
+    timing: with_params(&model.timing, &params),
+    intervals,
+    rms_before,
+    rms_after: rms(best),
+  })
+}
+
+pub fn print_fit(fit: &Fit) {
+  let t = &fit.timing;
+  println!("Fitted against {} intervals", fit.intervals);
+  println!("RMS error: {:.1}ms before, {:.1}ms after", fit.rms_before, fit.rms_after);
+  println!("Press duration: {}ms", t.press_dur);
+  println!("Gap between presses: {}ms", t.press_gap);
+  println!("Same hand wait: {}ms", t.same_hand);
+  for (i, speed) in t.finger_speed.iter().enumerate() {
+    println!("Finger {} speed: {:.1}ms/u", i, speed);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A session typed exactly like the simulation would with timing
+  fn simulated<'a>(text: &str, lay: &'a layout::Layout, timing: &model::Timing) -> Vec<keylog::KeyPress<'a>> {
+    let model = model::Model {
+      timing: timing.clone(),
+      ..Default::default()
+    };
+    let mut presses: Vec<keylog::KeyPress> = Vec::new();
+    for e in analyze::events(text, lay, &model) {
+      match e.kind {
+        event::EventKind::KeyDown => presses.push(keylog::KeyPress {
+          key: lay.key_by_name(&e.key).unwrap(),
+          down: e.time,
+          up: e.time,
+          ch: e.ch,
+          modifier: e.ch.is_none(),
+        }),
+        event::EventKind::KeyUp => {
+          let p = presses.iter_mut().rev().find(|p| p.key.visual.name == e.key).unwrap();
+          p.up = e.time;
+        }
+        _ => {}
+      }
+    }
+    presses
+  }
+
+  #[test]
+  fn recovers_timing() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    let truth = model::Timing {
+      press_dur: 80,
+      press_gap: 40,
+      move_speed: 120.0,
+      finger_speed: Vec::new(),
+      same_hand: 30,
+    };
+    let text = "the quick brown fox jumps over the lazy dog and keeps running far away";
+    let presses = simulated(text, lay, &truth);
+
+    let fit = calibrate(&presses, lay, &model::Model::default()).unwrap();
+    assert_eq!(fit.intervals, text.len() - 1);
+    assert_eq!(fit.timing.press_dur, 80);
+    assert!(fit.rms_after < fit.rms_before);
+    assert!(fit.rms_after < 5.0, "{}", fit.rms_after);
+    assert!((fit.timing.press_gap - truth.press_gap).abs() <= 5);
+  }
+
+  #[test]
+  fn split_into_runs() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    // Backspace and enter end runs, and "x" is left alone
+    let presses = keylog::read_log("test/session.log", lay).unwrap();
+    let runs = runs(&presses);
+    let texts: Vec<&str> = runs.iter().map(|r| r.text.as_str()).collect();
+    assert_eq!(texts, vec!["Hi x"]);
+    assert_eq!(runs[0].downs, vec![120, 300, 450, 600]);
+  }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use super::input;
+use super::layout;
+
+static MAX_CONTEXTS: usize = 3;
+static CONTEXT_CHARS: usize = 12; // chars shown on each side of an unmapped char
+
+#[derive(Default)]
+pub struct Unmapped {
+  pub count: u32,
+  pub contexts: Vec<String>, // the first few places the char appeared
+}
+
+// Which chars of a text a layout can and can't type
+#[derive(Default)]
+pub struct Coverage {
+  pub unmapped: BTreeMap<char, Unmapped>,
+  pub tags: BTreeMap<String, Unmapped>, // Tags the layout doesn't have the keys for
+  pub typed: u32,
+  pub total: u32,
+}
+
+impl Coverage {
+  pub fn percent(&self) -> f32 {
+    if self.total == 0 {
+      return 100.0;
+    }
+    (self.typed as f32) * 100.0 / (self.total as f32)
+  }
+
+  pub fn is_complete(&self) -> bool {
+    self.unmapped.is_empty() && self.tags.is_empty()
+  }
+}
+
+pub fn check(text: &str, lay: &layout::Layout) -> Coverage {
+  let mut cov = Coverage::default();
+
+  for stroke in input::strokes(text, lay) {
+    cov.total += stroke.chars;
+    let entry = match (stroke.ch, &stroke.combos) {
+      (Some(c), None) => cov.unmapped.entry(c).or_default(),
+      (None, None) => {
+        let rest = &text[stroke.idx..];
+        let tag = rest.find('>').map_or(rest, |end| &rest[..=end]);
+        cov.tags.entry(tag.to_string()).or_default()
+      }
+      _ => {
+        cov.typed += stroke.chars;
+        continue;
+      }
+    };
+
+    entry.count += 1;
+    if entry.contexts.len() < MAX_CONTEXTS {
+      let before: Vec<char> = text[..stroke.idx].chars().rev().take(CONTEXT_CHARS).collect();
+      let mut context: String = before.iter().rev().collect();
+      context.extend(text[stroke.idx..].chars().take(CONTEXT_CHARS + 1));
+      entry.contexts.push(context);
+    }
+  }
+
+  cov
+}
+
+pub fn print_report(cov: &Coverage) {
+  println!("Coverage: {:.2}% ({} of {} chars)", cov.percent(), cov.typed, cov.total);
+  if cov.is_complete() {
+    return;
+  }
+
+  let mut sorted: Vec<(&char, &Unmapped)> = cov.unmapped.iter().collect();
+  sorted.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+
+  if !sorted.is_empty() {
+    println!("Unmapped characters:");
+  }
+  for (c, info) in sorted {
+    println!("  {:?} (U+{:04X}): {}", c, *c as u32, info.count);
+    for context in &info.contexts {
+      println!("      {:?}", context);
+    }
+  }
+
+  if !cov.tags.is_empty() {
+    println!("Tags without the keys to press them:");
+  }
+  for (tag, info) in cov.tags.iter() {
+    println!("  {}: {}", tag, info.count);
+    for context in &info.contexts {
+      println!("      {:?}", context);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unmapped_chars() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    let cov = check("naïve café, très naïve", lay);
+
+    assert_eq!(cov.total, 22);
+    assert_eq!(cov.typed, 18);
+    assert!(!cov.is_complete());
+
+    let i_diaeresis = cov.unmapped.get(&'ï').unwrap();
+    assert_eq!(i_diaeresis.count, 2);
+    assert_eq!(i_diaeresis.contexts.len(), 2);
+    assert_eq!(i_diaeresis.contexts[0], "naïve café, trè");
+    assert_eq!(cov.unmapped.get(&'é').unwrap().count, 1);
+    assert_eq!(cov.unmapped.get(&'è').unwrap().count, 1);
+  }
+
+  #[test]
+  fn missing_modifier() {
+    // No ctrl key, so <C-w> can't be pressed
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/mobile-qwerty.layout").unwrap();
+
+    let cov = check("a<C-w>b<C-w>", lay);
+    assert_eq!((cov.typed, cov.total), (2, 4));
+    assert!(cov.unmapped.is_empty());
+    assert_eq!(cov.tags.get("<C-w>").unwrap().count, 2);
+    assert!(!cov.is_complete());
+  }
+
+  #[test]
+  fn full_coverage() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    let cov = check("The Quick Brown Fox Jumps Over The Lazy Dog.", lay);
+    assert!(cov.is_complete());
+    assert_eq!(cov.percent(), 100.0);
+  }
+}
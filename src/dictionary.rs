@@ -0,0 +1,238 @@
+use super::analyze;
+
+static PERCENTILES: [u32; 7] = [1, 10, 25, 50, 75, 90, 99];
+static HISTOGRAM_BINS: usize = 10;
+static HISTOGRAM_WIDTH: usize = 40; // Chars of the longest bar
+static OUTLIERS: usize = 10;
+
+// Spread of a stat over every word
+pub struct Summary {
+  pub min: f32,
+  pub max: f32,
+  pub mean: f32,
+  pub percentiles: Vec<(u32, f32)>, // (percentile, value)
+}
+
+// Least squares line of distance (u) by letter count, and how well
+// they correlate
+pub struct LengthFit {
+  pub intercept: f32,
+  pub slope: f32,
+  pub correlation: f32, // Pearson's r
+}
+
+impl LengthFit {
+  pub fn predict(&self, letters: u32) -> f32 {
+    self.intercept + self.slope * letters as f32
+  }
+}
+
+// Nearest rank percentiles of values
+pub fn summarize(values: &[f32]) -> Summary {
+  let mut sorted = values.to_vec();
+  sorted.sort_by(|a, b| a.total_cmp(b));
+  if sorted.is_empty() {
+    sorted.push(0.0);
+  }
+
+  let n = sorted.len();
+  let percentiles = PERCENTILES
+    .iter()
+    .map(|p| (*p, sorted[((*p as f32 / 100.0) * (n - 1) as f32).round() as usize]))
+    .collect();
+  Summary {
+    min: sorted[0],
+    max: sorted[n - 1],
+    mean: sorted.iter().sum::<f32>() / n as f32,
+    percentiles,
+  }
+}
+
+// Counts of values in bins equal parts of the way from the smallest
+// to the largest, with the start of each bin
+pub fn histogram(values: &[f32], bins: usize) -> Vec<(f32, usize)> {
+  let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+  let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+  if values.is_empty() {
+    return Vec::new();
+  }
+
+  let width = (max - min) / bins as f32;
+  let mut counts = vec![0; bins];
+  for v in values {
+    let bin = if width > 0.0 { ((v - min) / width) as usize } else { 0 };
+    counts[bin.min(bins - 1)] += 1;
+  }
+  counts.into_iter().enumerate().map(|(i, c)| (min + width * i as f32, c)).collect()
+}
+
+pub fn fit_length(words: &[analyze::RankedLine]) -> LengthFit {
+  let n = words.len() as f32;
+  let xs: Vec<f32> = words.iter().map(|w| w.tl.total_chars as f32).collect();
+  let ys: Vec<f32> = words.iter().map(|w| w.tl.total_dist).collect();
+  let mean_x = xs.iter().sum::<f32>() / n;
+  let mean_y = ys.iter().sum::<f32>() / n;
+
+  let mut cov = 0.0;
+  let mut var_x = 0.0;
+  let mut var_y = 0.0;
+  for (x, y) in xs.iter().zip(ys.iter()) {
+    cov += (x - mean_x) * (y - mean_y);
+    var_x += (x - mean_x).powi(2);
+    var_y += (y - mean_y).powi(2);
+  }
+
+  let slope = if var_x > 0.0 { cov / var_x } else { 0.0 };
+  let correlation = if var_x > 0.0 && var_y > 0.0 { cov / (var_x * var_y).sqrt() } else { 0.0 };
+  LengthFit {
+    intercept: mean_y - slope * mean_x,
+    slope,
+    correlation,
+  }
+}
+
+// Words by how much longer (in u) they are than their letter count
+// predicts, most first. The shortest for their length are at the end
+pub fn by_residual<'a>(words: &'a [analyze::RankedLine], fit: &LengthFit) -> Vec<(&'a analyze::RankedLine, f32)> {
+  let mut res: Vec<(&analyze::RankedLine, f32)> =
+    words.iter().map(|w| (w, w.tl.total_dist - fit.predict(w.tl.total_chars))).collect();
+  res.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.line.cmp(&b.0.line)));
+  res
+}
+
+fn summary_string(name: &str, values: &[f32]) -> String {
+  let s = summarize(values);
+  let mut res = format!("{}: min {:.2}, mean {:.2}, max {:.2}\n ", name, s.min, s.mean, s.max);
+  for (p, v) in s.percentiles.iter() {
+    res.push_str(&format!(" p{} {:.2}", p, v));
+  }
+
+  let bins = histogram(values, HISTOGRAM_BINS);
+  let most = bins.iter().map(|(_, c)| *c).max().unwrap_or(0).max(1);
+  for (start, count) in bins {
+    let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(most));
+    res.push_str(&format!("\n  {:>10.2} {:>8} {}", start, count, bar));
+  }
+  res
+}
+
+// words come from analyze::compare_lines, which leaves out blank lines
+// so they don't count as words that cost nothing
+pub fn print_report(words: &[analyze::RankedLine]) {
+  if words.is_empty() {
+    println!("No words to summarize");
+    return;
+  }
+
+  let dists: Vec<f32> = words.iter().map(|w| w.tl.total_dist).collect();
+  let times: Vec<f32> = words.iter().map(|w| w.tl.total_time as f32).collect();
+  let per_char: Vec<f32> = words.iter().filter(|w| w.tl.total_chars > 0).map(|w| w.tl.u_per_char()).collect();
+
+  println!("\n{} words", words.len());
+  println!("{}", summary_string("Distance (u)", &dists));
+  println!("{}", summary_string("Time (ms)", &times));
+  println!("{}", summary_string("Distance per letter (u)", &per_char));
+
+  let fit = fit_length(words);
+  println!(
+    "\nDistance = {:.3}u + {:.3}u per letter, correlation with letter count {:.3}",
+    fit.intercept, fit.slope, fit.correlation
+  );
+
+  let ranked = by_residual(words, &fit);
+  // Short lists are split between the two so no line is in both
+  let n = OUTLIERS.min(ranked.len() / 2);
+  println!("Longest for their length:");
+  for (w, r) in ranked.iter().take(n) {
+    println!("  {} ({} letters): {:.2}u, {:+.2}u", w.line, w.tl.total_chars, w.tl.total_dist, r);
+  }
+  println!("Shortest for their length:");
+  for (w, r) in ranked.iter().rev().take(n) {
+    println!("  {} ({} letters): {:.2}u, {:+.2}u", w.line, w.tl.total_chars, w.tl.total_dist, r);
+  }
+}
+
+// Every word with its stats and how far it is from what its length
+// predicts
+pub fn write_csv(words: &[analyze::RankedLine], path: &str) -> csv::Result<()> {
+  let fit = fit_length(words);
+  let mut writer = csv::Writer::from_path(path)?;
+  writer.write_record(["word", "letters", "dist_u", "time_ms", "dist_per_letter_u", "predicted_dist_u", "residual_u"])?;
+
+  for (w, r) in by_residual(words, &fit) {
+    writer.write_record(&[
+      w.line.clone(),
+      w.tl.total_chars.to_string(),
+      w.tl.total_dist.to_string(),
+      w.tl.total_time.to_string(),
+      w.tl.u_per_char().to_string(),
+      fit.predict(w.tl.total_chars).to_string(),
+      r.to_string(),
+    ])?;
+  }
+
+  writer.flush()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::{layout, model};
+  use super::*;
+
+  #[test]
+  fn stats() {
+    let values: Vec<f32> = (1..=101).map(|i| i as f32).collect();
+    let s = summarize(&values);
+    assert_eq!((s.min, s.max, s.mean), (1.0, 101.0, 51.0));
+    assert_eq!(s.percentiles[3], (50, 51.0));
+    assert_eq!(s.percentiles[6], (99, 100.0));
+
+    let bins = histogram(&values, 10);
+    assert_eq!(bins.len(), 10);
+    assert_eq!(bins[0], (1.0, 10));
+    // The largest value goes in the last bin
+    assert_eq!(bins[9].1, 11);
+    assert_eq!(histogram(&[3.0, 3.0], 4), vec![(3.0, 2), (3.0, 0), (3.0, 0), (3.0, 0)]);
+  }
+
+  #[test]
+  fn words() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let all = analyze::Ranking {
+      count: usize::MAX,
+      ..Default::default()
+    };
+    let words = analyze::compare_lines("fj\nfjfj\nfjfjfj\nqz", lay, &model::Model::default(), &all);
+
+    // fj words cost nothing however long they are
+    let fit = fit_length(&words[1..]);
+    assert_eq!((fit.intercept, fit.slope, fit.correlation), (0.0, 0.0, 0.0));
+
+    let fit = fit_length(&words);
+    assert!(fit.slope < 0.0);
+    let ranked = by_residual(&words, &fit);
+    assert_eq!(ranked[0].0.line, "qz");
+    // qz pulls the line up at 2 letters, so fj is furthest below it
+    assert_eq!(ranked.last().unwrap().0.line, "fj");
+  }
+
+  #[test]
+  fn blank_lines() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let all = analyze::Ranking {
+      count: usize::MAX,
+      ..Default::default()
+    };
+    let words = analyze::compare_lines("fj\nfjfj\nfjfjfj\nqz", lay, &model::Model::default(), &all);
+    let spaced = analyze::compare_lines("\nfj\n\nfjfj\n \nfjfjfj\n\nqz\n\n", lay, &model::Model::default(), &all);
+
+    // Blank lines aren't zero cost words
+    assert_eq!(spaced.len(), words.len());
+    let dists = |ws: &[analyze::RankedLine]| -> Vec<f32> { ws.iter().map(|w| w.tl.total_dist).collect() };
+    assert_eq!(summarize(&dists(&spaced)).mean, summarize(&dists(&words)).mean);
+    assert_eq!(fit_length(&spaced).correlation, fit_length(&words).correlation);
+  }
+}
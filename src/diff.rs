@@ -0,0 +1,286 @@
+use super::analyze;
+use super::layout;
+
+// Keys whose centers are closer than this (in u) are the same key
+static MATCH_DIST: f32 = 0.3;
+
+// Text columns per u in the keyboard picture
+static PICTURE_SCALE: f32 = 4.0;
+
+// A key at the same position in both layouts, or one only in one of them
+pub struct KeyDiff<'a> {
+  pub old: Option<&'a layout::Key>,
+  pub new: Option<&'a layout::Key>,
+  pub changes: Vec<String>,
+}
+
+impl<'a> KeyDiff<'a> {
+  pub fn is_changed(&self) -> bool {
+    !self.changes.is_empty()
+  }
+}
+
+// A char typed with a different key in the new layout
+pub struct MovedChar<'a> {
+  pub c: char,
+  pub old: Option<&'a layout::Key>, // None if it can't be typed
+  pub new: Option<&'a layout::Key>,
+}
+
+fn all_keys<'a>(lay: &'a layout::Layout) -> Vec<&'a layout::Key> {
+  let mut keys: Vec<&layout::Key> = lay.keys.iter().chain(lay.mod_map.values()).collect();
+  keys.sort_by(|a, b| a.pos.y.partial_cmp(&b.pos.y).unwrap().then(a.pos.x.partial_cmp(&b.pos.x).unwrap()));
+  keys
+}
+
+// Pair up keys by position, closest first
+pub fn match_keys<'a>(old: &'a layout::Layout, new: &'a layout::Layout) -> Vec<KeyDiff<'a>> {
+  let old_keys = all_keys(old);
+  let mut new_keys: Vec<Option<&layout::Key>> = all_keys(new).into_iter().map(Some).collect();
+
+  let mut res = Vec::new();
+  for key in old_keys {
+    let closest = new_keys
+      .iter()
+      .enumerate()
+      .filter_map(|(i, k)| k.map(|k| (i, analyze::move_dist(&key.center(), &k.center()))))
+      .filter(|(_, d)| *d < MATCH_DIST)
+      .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+    let other = closest.and_then(|(i, _)| new_keys[i].take());
+    res.push(KeyDiff {
+      old: Some(key),
+      new: other,
+      changes: Vec::new(),
+    });
+  }
+  for key in new_keys.into_iter().flatten() {
+    res.push(KeyDiff {
+      old: None,
+      new: Some(key),
+      changes: Vec::new(),
+    });
+  }
+
+  for kd in res.iter_mut() {
+    kd.changes = match (kd.old, kd.new) {
+      (Some(o), Some(n)) => key_changes(o, n),
+      (Some(_), None) => vec!["removed".to_string()],
+      _ => vec!["added".to_string()],
+    };
+  }
+
+  res
+}
+
+fn chars_of(key: &layout::Key) -> String {
+  let mut res: String = [key.pressed, key.shifted].iter().chain(key.levels.iter()).filter(|c| **c != '\0').collect();
+  if res.is_empty() {
+    res.push('-');
+  }
+  res
+}
+
+fn key_changes(old: &layout::Key, new: &layout::Key) -> Vec<String> {
+  let mut res = Vec::new();
+
+  if old.visual.name != new.visual.name {
+    res.push(format!("renamed to {}", new.visual.name));
+  }
+  if chars_of(old) != chars_of(new) {
+    res.push(format!("chars {} -> {}", chars_of(old), chars_of(new)));
+  }
+  if old.finger != new.finger {
+    res.push(format!("finger {} -> {}", old.finger, new.finger));
+  }
+  if old.is_home != new.is_home {
+    res.push(if new.is_home { "now a home" } else { "no longer a home" }.to_string());
+  }
+
+  let (oc, nc) = (old.center(), new.center());
+  if analyze::move_dist(&oc, &nc) > 0.001 {
+    res.push(format!("moved by ({:.2}, {:.2})u", nc.x - oc.x, nc.y - oc.y));
+  }
+  if old.visual.width != new.visual.width || old.visual.height != new.visual.height {
+    res.push(format!(
+      "size {}x{} -> {}x{}",
+      old.visual.width, old.visual.height, new.visual.width, new.visual.height
+    ));
+  }
+  if old.visual.rotation != new.visual.rotation {
+    res.push(format!("rotation {} -> {}", old.visual.rotation, new.visual.rotation));
+  }
+
+  res
+}
+
+// Chars whose key in the new layout isn't at the position of their
+// old key
+pub fn moved_chars<'a>(old: &'a layout::Layout, new: &'a layout::Layout, keys: &[KeyDiff]) -> Vec<MovedChar<'a>> {
+  let mut chars: Vec<char> = old.char_keys.keys().chain(new.char_keys.keys()).copied().collect();
+  chars.sort_unstable();
+  chars.dedup();
+
+  let mut res = Vec::new();
+  for c in chars {
+    let old_key = old.char_keys.get(&c).map(|co| co.key);
+    let new_key = new.char_keys.get(&c).map(|co| co.key);
+
+    let same_place = match (old_key, new_key) {
+      (Some(o), Some(n)) => keys
+        .iter()
+        .any(|kd| kd.old.is_some_and(|k| std::ptr::eq(k, o)) && kd.new.is_some_and(|k| std::ptr::eq(k, n))),
+      (None, None) => true,
+      _ => false,
+    };
+    if !same_place {
+      res.push(MovedChar {
+        c,
+        old: old_key,
+        new: new_key,
+      });
+    }
+  }
+
+  res
+}
+
+// Draw the keys as text, one line per u, with changed keys in * instead
+// of brackets
+pub fn picture(keys: &[&layout::Key], changed: &[&layout::Key]) -> String {
+  let mut lines: Vec<Vec<char>> = Vec::new();
+
+  for key in keys {
+    let row = key.center().y.floor().max(0.0) as usize;
+    let col = (key.pos.x * PICTURE_SCALE).round().max(0.0) as usize;
+    let width = ((key.visual.width * PICTURE_SCALE).round() as usize).max(3);
+
+    let label: String = match key.pressed {
+      '\0' | ' ' => key.visual.name.chars().take(width - 2).collect(),
+      c => c.to_string(),
+    };
+    let (open, close) = if changed.iter().any(|k| std::ptr::eq(*k, *key)) {
+      ('*', '*')
+    } else {
+      ('[', ']')
+    };
+    let text = format!("{}{:^w$}{}", open, label, close, w = width - 2);
+
+    while lines.len() <= row {
+      lines.push(Vec::new());
+    }
+    let line = &mut lines[row];
+    for (i, ch) in text.chars().enumerate() {
+      while line.len() <= col + i {
+        line.push(' ');
+      }
+      line[col + i] = ch;
+    }
+  }
+
+  let lines: Vec<String> = lines.iter().map(|l| l.iter().collect::<String>().trim_end().to_string()).collect();
+  lines.join("\n")
+}
+
+pub fn print_diff(old: &layout::Layout, new: &layout::Layout) {
+  let keys = match_keys(old, new);
+  let changed: Vec<&KeyDiff> = keys.iter().filter(|kd| kd.is_changed()).collect();
+
+  println!("Old:");
+  let old_changed: Vec<&layout::Key> = changed.iter().filter_map(|kd| kd.old).collect();
+  println!("{}", picture(&all_keys(old), &old_changed));
+  println!("New:");
+  let new_changed: Vec<&layout::Key> = changed.iter().filter_map(|kd| kd.new).collect();
+  println!("{}", picture(&all_keys(new), &new_changed));
+
+  println!("\n{} changed key(s):", changed.len());
+  for kd in &changed {
+    let key = kd.old.or(kd.new).unwrap();
+    println!(
+      "  {} at ({:.2}, {:.2}): {}",
+      key.visual.name,
+      key.pos.x,
+      key.pos.y,
+      kd.changes.join(", ")
+    );
+  }
+
+  let moved = moved_chars(old, new, &keys);
+  println!("\n{} moved char(s):", moved.len());
+  for m in moved {
+    let place = |k: Option<&layout::Key>| match k {
+      Some(k) => format!("{} at ({:.2}, {:.2})", k.visual.name, k.pos.x, k.pos.y),
+      None => "(none)".to_string(),
+    };
+    println!("  {:?}: {} -> {}", m.c, place(m.old), place(m.new));
+  }
+}
+
+// The change in each headline stat from old to new
+pub fn print_metrics(old: &analyze::Timeline, new: &analyze::Timeline) {
+  let rows = [
+    ("Distance (u)", old.total_dist, new.total_dist),
+    ("Distance per char (u)", old.u_per_char(), new.u_per_char()),
+    ("Strokes per char", old.strokes_per_char(), new.strokes_per_char()),
+    ("Time (ms)", old.total_time as f32, new.total_time as f32),
+    ("Coverage %", old.coverage_percent(), new.coverage_percent()),
+    ("Errors", old.total_errors() as f32, new.total_errors() as f32),
+    ("% Alternating", old.alternating_percent() as f32, new.alternating_percent() as f32),
+    ("% Thumbs", old.thumb_percent() as f32, new.thumb_percent() as f32),
+    ("% Home row", old.home_row_percent(), new.home_row_percent()),
+    ("% Stretch columns", old.stretch_percent(), new.stretch_percent()),
+    ("WPM", old.wpm() as f32, new.wpm() as f32),
+  ];
+
+  println!("\n{:<24} {:>10} {:>10} {:>10}", "Metric", "Old", "New", "Change");
+  for (name, o, n) in rows.iter() {
+    println!("{:<24} {:>10.2} {:>10.2} {:>+10.2}", name, o, n, n - o);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn qwerty_to_colemak() {
+    let mut old = layout::Layout::default();
+    let old = layout::init(&mut old, "layouts/qwerty.layout").unwrap();
+    let mut new = layout::Layout::default();
+    let new = layout::init(&mut new, "layouts/colemak.layout").unwrap();
+
+    let keys = match_keys(old, new);
+    assert!(keys.iter().all(|kd| kd.old.is_some() && kd.new.is_some()));
+
+    // Colemak's backspace is where caps lock was
+    let caps = keys.iter().find(|kd| kd.old.unwrap().visual.name == "caps").unwrap();
+    assert_eq!(caps.changes, vec!["renamed to backspace"]);
+
+    let moved = moved_chars(old, new, &keys);
+    let e = moved.iter().find(|m| m.c == 'e').unwrap();
+    assert_eq!(e.old.unwrap().pos.x, 3.5);
+    assert_eq!(e.new.unwrap().pos.x, 8.75);
+    assert!(moved.iter().all(|m| m.c != 'a' && m.c != 'A'));
+    // Only 16 letters move
+    assert_eq!(moved.iter().filter(|m| m.c.is_ascii_lowercase()).count(), 16);
+  }
+
+  #[test]
+  fn geometry() {
+    let mut old = layout::Layout::default();
+    let old = layout::init_mapped(&mut old, "boards/ansi.board", "mappings/qwerty.map").unwrap();
+    let mut new = layout::Layout::default();
+    let new = layout::init_mapped(&mut new, "boards/iso.board", "mappings/qwerty.map").unwrap();
+
+    let keys = match_keys(old, new);
+    let added: Vec<&str> = keys.iter().filter(|kd| kd.old.is_none()).map(|kd| kd.new.unwrap().visual.name.as_str()).collect();
+    assert!(added.contains(&"iso"));
+
+    let lshift = keys.iter().find(|kd| kd.old.is_some_and(|k| k.visual.name == "lshift")).unwrap();
+    assert!(lshift.new.is_none());
+
+    let pic = picture(&all_keys(new), &[]);
+    assert!(pic.lines().nth(2).unwrap().starts_with("[caps"));
+    assert!(pic.contains("[q ]"));
+  }
+}
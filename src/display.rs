@@ -1,3 +1,4 @@
+use super::breakdown;
 use super::layout;
 use super::playback;
 use sdl2::gfx::primitives::DrawRenderer;
@@ -27,6 +28,7 @@ static KEY_RAD: i16 = 10;
 static KEY_COL: Color = Color::RGB(0, 0, 0);
 static FING_COL: Color = Color::RGB(25, 128, 255);
 static TEXT_COL: Color = Color::RGB(0, 0, 0);
+static SLOW_COL: Color = Color::RGB(230, 0, 0); // Of the slowest word
 static BG_COL: Color = Color::RGB(250, 250, 255);
 static TEXT_VSTEP: i32 = 15;
 
@@ -66,6 +68,43 @@ pub fn draw_text(x: i32, y: i32, text: &str, data: &mut Data) {
   }
 }
 
+// Draw text on one line in quotes like the plain text, with each word
+// in words coloured from TEXT_COL to SLOW_COL by how long it took
+// compared to the slowest
+pub fn draw_words(x: i32, y: i32, text: &str, words: &[breakdown::Span], data: &mut Data) {
+  let slowest = words.iter().map(|w| w.time).max().unwrap_or(0).max(1);
+  let mix = |a: u8, b: u8, t: f32| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+
+  // Everything between words is drawn in TEXT_COL
+  let mut parts = vec![("\"", TEXT_COL)];
+  let mut idx = 0;
+  for w in words {
+    parts.push((&text[idx..w.start], TEXT_COL));
+    let t = w.time as f32 / slowest as f32;
+    let col = Color::RGB(mix(TEXT_COL.r, SLOW_COL.r, t), mix(TEXT_COL.g, SLOW_COL.g, t), mix(TEXT_COL.b, SLOW_COL.b, t));
+    parts.push((w.text(text), col));
+    idx = w.end;
+  }
+  parts.push((&text[idx..], TEXT_COL));
+  parts.push(("\"", TEXT_COL));
+
+  let mut x_offset = 0;
+  for (part, col) in parts {
+    let part = part.replace(['\n', '\t'], " ");
+    if part.is_empty() {
+      continue;
+    }
+    let surface = data.font.render(&part).blended(col).unwrap();
+    let creator = data.canvas.texture_creator();
+    let texture = creator.create_texture_from_surface(&surface).unwrap();
+
+    let TextureQuery { width, height, .. } = texture.query();
+    let pos = Rect::new(x + x_offset, y, width, height);
+    data.canvas.copy(&texture, None, pos).unwrap();
+    x_offset += width as i32;
+  }
+}
+
 pub fn clear_screen(disp_data: &mut Data) {
   disp_data.canvas.set_draw_color(BG_COL);
   disp_data.canvas.clear();
@@ -86,6 +125,18 @@ pub fn draw_playdata(playdata: &playback::PlayData, disp_data: &mut Data) {
   }
 }
 
+// Rotated keys are drawn as plain polygons around their center
+fn draw_rotated_key(key: &layout::Key, data: &mut Data) {
+  let mut vx = Vec::new();
+  let mut vy = Vec::new();
+  for corner in key.corners().iter() {
+    vx.push((corner.x * KEY_W) as i16);
+    vy.push((corner.y * KEY_H) as i16);
+  }
+
+  data.canvas.polygon(&vx, &vy, KEY_COL).unwrap();
+}
+
 pub fn draw_layout(lay: &layout::Layout, data: &mut Data) {
   for key in &lay.keys {
     draw_key(key, data);
@@ -99,12 +150,16 @@ fn draw_key(key: &layout::Key, data: &mut Data) {
   let x1 = (key.pos.x * KEY_W) as i16;
   let y1 = (key.pos.y * KEY_H) as i16;
   let x2 = x1 + ((KEY_W * key.visual.width) as i16);
-  let y2 = y1 + (KEY_W as i16);
-
-  data
-    .canvas
-    .rounded_rectangle(x1, y1, x2, y2, KEY_RAD, KEY_COL)
-    .unwrap();
+  let y2 = y1 + ((KEY_H * key.visual.height) as i16);
+
+  if key.visual.rotation == 0.0 {
+    data
+      .canvas
+      .rounded_rectangle(x1, y1, x2, y2, KEY_RAD, KEY_COL)
+      .unwrap();
+  } else {
+    draw_rotated_key(key, data);
+  }
 
   draw_text(
     (x1 + (KEY_RAD / 2)) as i32,
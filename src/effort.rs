@@ -0,0 +1,339 @@
+use super::analyze;
+use super::input;
+use super::layout;
+
+// Weights of a Carpalx style effort model. The effort of a triad of
+// presses is kb * base + kp * penalty + ks * path, where base and
+// penalty combine the three keys' efforts as
+// k1 * e1 * (1 + k2 * e2 * (1 + k3 * e3)), so later keys only add
+// effort on top of earlier ones
+#[derive(Clone)]
+pub struct EffortConfig {
+  pub kb: f32,
+  pub kp: f32,
+  pub ks: f32,
+  pub k1: f32,
+  pub k2: f32,
+  pub k3: f32,
+
+  // Base effort of a key by its row, plus base_stretch if it's in a
+  // stretch column. Indexed by analyze::Row
+  pub base_rows: [f32; 5],
+  pub base_stretch: f32,
+
+  // A key's penalty is w0 + wh * hand + wr * row + wf * finger
+  pub w0: f32,
+  pub wh: f32,
+  pub wr: f32,
+  pub wf: f32,
+  pub hand_left: f32,
+  pub hand_right: f32,
+  pub rows: [f32; 5],    // Indexed by analyze::Row
+  pub fingers: [f32; 5], // Thumb, index, middle, ring, pinky
+
+  // A triad's path effort is fh * hands + fr * rows + ff * fingers,
+  // see path_effort
+  pub fh: f32,
+  pub fr: f32,
+  pub ff: f32,
+}
+
+// Carpalx's published defaults
+impl Default for EffortConfig {
+  fn default() -> Self {
+    EffortConfig {
+      kb: 0.3555,
+      kp: 0.6423,
+      ks: 0.4268,
+      k1: 1.0,
+      k2: 0.367,
+      k3: 0.235,
+      base_rows: [4.0, 2.0, 0.0, 2.0, 0.0],
+      base_stretch: 1.5,
+      w0: 0.0,
+      wh: 1.0,
+      wr: 1.3088,
+      wf: 2.5948,
+      hand_left: 0.0,
+      hand_right: 0.0,
+      rows: [1.5, 0.5, 0.0, 1.0, 0.0],
+      fingers: [0.0, 0.0, 0.0, 0.5, 1.0],
+      fh: 1.0,
+      fr: 0.3,
+      ff: 0.3,
+    }
+  }
+}
+
+// Effort files are CSV files with a name,value header and a row per
+// weight to change from the defaults. Lines starting with # are
+// comments
+pub fn read_config(path: &str) -> Option<EffortConfig> {
+  let mut reader = match csv::ReaderBuilder::new().comment(Some(b'#')).trim(csv::Trim::All).from_path(path) {
+    Ok(r) => r,
+    Err(e) => {
+      println!("{}", e);
+      return None;
+    }
+  };
+
+  let mut cfg = EffortConfig::default();
+  for res in reader.records() {
+    let record = match res {
+      Ok(r) => r,
+      Err(e) => {
+        println!("{}", e);
+        return None;
+      }
+    };
+
+    let name = record.get(0)?;
+    let value = match record.get(1).and_then(|v| v.parse::<f32>().ok()) {
+      Some(v) => v,
+      None => {
+        println!("Effort weight {} needs a number", name);
+        return None;
+      }
+    };
+
+    let weight = match name {
+      "kb" => &mut cfg.kb,
+      "kp" => &mut cfg.kp,
+      "ks" => &mut cfg.ks,
+      "k1" => &mut cfg.k1,
+      "k2" => &mut cfg.k2,
+      "k3" => &mut cfg.k3,
+      "base_number" => &mut cfg.base_rows[analyze::Row::Number as usize],
+      "base_top" => &mut cfg.base_rows[analyze::Row::Top as usize],
+      "base_home" => &mut cfg.base_rows[analyze::Row::Home as usize],
+      "base_bottom" => &mut cfg.base_rows[analyze::Row::Bottom as usize],
+      "base_thumb" => &mut cfg.base_rows[analyze::Row::Thumb as usize],
+      "base_stretch" => &mut cfg.base_stretch,
+      "w0" => &mut cfg.w0,
+      "wh" => &mut cfg.wh,
+      "wr" => &mut cfg.wr,
+      "wf" => &mut cfg.wf,
+      "hand_left" => &mut cfg.hand_left,
+      "hand_right" => &mut cfg.hand_right,
+      "row_number" => &mut cfg.rows[analyze::Row::Number as usize],
+      "row_top" => &mut cfg.rows[analyze::Row::Top as usize],
+      "row_home" => &mut cfg.rows[analyze::Row::Home as usize],
+      "row_bottom" => &mut cfg.rows[analyze::Row::Bottom as usize],
+      "row_thumb" => &mut cfg.rows[analyze::Row::Thumb as usize],
+      "finger_thumb" => &mut cfg.fingers[0],
+      "finger_index" => &mut cfg.fingers[1],
+      "finger_middle" => &mut cfg.fingers[2],
+      "finger_ring" => &mut cfg.fingers[3],
+      "finger_pinky" => &mut cfg.fingers[4],
+      "fh" => &mut cfg.fh,
+      "fr" => &mut cfg.fr,
+      "ff" => &mut cfg.ff,
+      unknown => {
+        println!("Unknown effort weight {}", unknown);
+        return None;
+      }
+    };
+    *weight = value;
+  }
+
+  Some(cfg)
+}
+
+// Summed effort of every triad, already weighted by kb, kp and ks
+#[derive(Default, Clone, Copy)]
+pub struct Effort {
+  pub base: f32,
+  pub penalty: f32,
+  pub path: f32,
+  pub triads: u32,
+}
+
+impl Effort {
+  // Average effort per triad
+  pub fn per_triad(&self) -> f32 {
+    if self.triads == 0 {
+      return 0.0;
+    }
+    (self.base + self.penalty + self.path) / (self.triads as f32)
+  }
+
+  pub fn add(&mut self, other: &Effort) {
+    self.base += other.base;
+    self.penalty += other.penalty;
+    self.path += other.path;
+    self.triads += other.triads;
+  }
+}
+
+// A press as far as effort is concerned
+#[derive(Clone, Copy)]
+struct Press<'a> {
+  key: &'a layout::Key,
+  finger: usize,
+  hand: layout::Hand,
+  row: analyze::Row,
+  base: f32,
+  penalty: f32,
+}
+
+fn finger_index(kind: layout::FingerKind) -> usize {
+  match kind {
+    layout::FingerKind::Thumb => 0,
+    layout::FingerKind::Index => 1,
+    layout::FingerKind::Middle => 2,
+    layout::FingerKind::Ring => 3,
+    layout::FingerKind::Pinky => 4,
+  }
+}
+
+// Effort of typing text, over every three presses in a row. Like
+// Carpalx, modifiers are left out, and thumbs and chars the layout
+// can't type break up the runs triads are taken from
+pub fn effort(text: &str, lay: &layout::Layout, cfg: &EffortConfig) -> Effort {
+  let own_columns = analyze::own_columns(lay);
+  let mut res = Effort::default();
+  let mut run: Vec<Press> = Vec::new();
+
+  for stroke in input::strokes(text, lay) {
+    let combos = match stroke.combos {
+      Some(cs) => cs,
+      None => {
+        run.clear();
+        continue;
+      }
+    };
+
+    for combo in combos.iter() {
+      let key = combo.key;
+      let finger = match lay.fingers.get(key.finger as usize) {
+        Some(f) if key.finger >= 0 && !f.is_thumb() => f,
+        _ => {
+          run.clear();
+          continue;
+        }
+      };
+
+      let (row, stretch) = analyze::key_place(lay, &own_columns, key.finger as usize, key);
+      let hand = match finger.hand {
+        layout::Hand::Left => cfg.hand_left,
+        layout::Hand::Right => cfg.hand_right,
+      };
+      run.push(Press {
+        key,
+        finger: key.finger as usize,
+        hand: finger.hand,
+        row,
+        base: cfg.base_rows[row as usize] + if stretch { cfg.base_stretch } else { 0.0 },
+        penalty: cfg.w0
+          + cfg.wh * hand
+          + cfg.wr * cfg.rows[row as usize]
+          + cfg.wf * cfg.fingers[finger_index(finger.kind)],
+      });
+
+      if run.len() >= 3 {
+        let t = &run[run.len() - 3..];
+        let combine = |e: [f32; 3]| cfg.k1 * e[0] * (1.0 + cfg.k2 * e[1] * (1.0 + cfg.k3 * e[2]));
+        res.base += cfg.kb * combine([t[0].base, t[1].base, t[2].base]);
+        res.penalty += cfg.kp * combine([t[0].penalty, t[1].penalty, t[2].penalty]);
+        res.path += cfg.ks * path_effort(t, cfg);
+        res.triads += 1;
+      }
+    }
+  }
+
+  res
+}
+
+// How awkward the path through a triad is, from 0 for the best case:
+// hands: 0 alternating, 1 two in a row on one hand, 2 all on one hand
+// rows: 0 same row, 1 one way with a repeat, 2 one way, 3 back and
+// forth by a row, 4 back and forth by more
+// fingers: 0 rolling one way, 1 rolling with a repeated key, 2 back
+// and forth, 3 back and forth with a repeated key, 4 one finger on
+// two different keys in a row
+fn path_effort(t: &[Press], cfg: &EffortConfig) -> f32 {
+  let hands = if t[0].hand == t[1].hand && t[1].hand == t[2].hand {
+    2.0
+  } else if t[0].hand == t[2].hand {
+    0.0
+  } else {
+    1.0
+  };
+
+  let r: Vec<i32> = t.iter().map(|p| p.row as i32).collect();
+  let rows = if r[0] == r[1] && r[1] == r[2] {
+    0.0
+  } else if is_monotonic(&r) {
+    if r[0] == r[1] || r[1] == r[2] {
+      1.0
+    } else {
+      2.0
+    }
+  } else if (r[1] - r[0]).abs() <= 1 && (r[2] - r[1]).abs() <= 1 {
+    3.0
+  } else {
+    4.0
+  };
+
+  let f: Vec<i32> = t.iter().map(|p| p.finger as i32).collect();
+  let same_key = |a: &Press, b: &Press| std::ptr::eq(a.key, b.key);
+  let repeat = same_key(&t[0], &t[1]) || same_key(&t[1], &t[2]);
+  let same_finger_jump =
+    (f[0] == f[1] && !same_key(&t[0], &t[1])) || (f[1] == f[2] && !same_key(&t[1], &t[2]));
+  let fingers = if same_finger_jump {
+    4.0
+  } else if is_monotonic(&f) {
+    if repeat {
+      1.0
+    } else {
+      0.0
+    }
+  } else if repeat {
+    3.0
+  } else {
+    2.0
+  };
+
+  cfg.fh * hands + cfg.fr * rows + cfg.ff * fingers
+}
+
+fn is_monotonic(v: &[i32]) -> bool {
+  v.windows(2).all(|w| w[0] <= w[1]) || v.windows(2).all(|w| w[0] >= w[1])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn home_row_is_easiest() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let cfg = EffortConfig::default();
+
+    // a s d is on the home row and rolls one way, with no penalties
+    let asd = effort("asd", lay, &cfg);
+    assert_eq!(asd.triads, 1);
+    assert_eq!(asd.base, 0.0);
+    // Only the pinky and ring finger have penalties
+    assert_eq!(asd.penalty, cfg.kp * cfg.wf * (1.0 + cfg.k2 * cfg.wf * 0.5));
+    assert_eq!(asd.path, cfg.ks * cfg.fh * 2.0);
+
+    // Alternating is cheaper than one hand
+    assert!(effort("ajs", lay, &cfg).path < asd.path);
+    assert!(effort("qzq", lay, &cfg).per_triad() > effort("asd", lay, &cfg).per_triad());
+
+    // Spaces break up triads
+    assert_eq!(effort("as df", lay, &cfg).triads, 0);
+    assert_eq!(effort("asdf jkl", lay, &cfg).triads, 3);
+  }
+
+  #[test]
+  fn config_file() {
+    let cfg = read_config("models/carpalx.effort").unwrap();
+    let default = EffortConfig::default();
+    assert_eq!(cfg.kb, default.kb);
+    assert_eq!(cfg.fingers, default.fingers);
+    assert_eq!(cfg.base_rows, default.base_rows);
+  }
+}
@@ -0,0 +1,38 @@
+// What happened in an Event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+  MoveStart, // A finger leaves for a key
+  MoveEnd,   // and gets there
+  KeyDown,
+  KeyUp,
+}
+
+// One thing a finger did during a simulation, see analyze::events
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+  pub time: i32, // ms
+  pub kind: EventKind,
+  pub finger: usize,
+  pub key: String, // Name of the key pressed or moved to
+  // Char of the text the key is pressed or moved to for. None for
+  // modifiers, typos, tags and fingers going back home
+  pub ch: Option<char>,
+}
+
+pub fn write_csv<I: Iterator<Item = Event>>(events: I, path: &str) -> csv::Result<()> {
+  let mut writer = csv::Writer::from_path(path)?;
+  writer.write_record(["time_ms", "event", "finger", "key", "char"])?;
+
+  for e in events {
+    writer.write_record(&[
+      e.time.to_string(),
+      format!("{:?}", e.kind),
+      e.finger.to_string(),
+      e.key,
+      e.ch.map_or(String::new(), |c| c.to_string()),
+    ])?;
+  }
+
+  writer.flush()?;
+  Ok(())
+}
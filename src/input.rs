@@ -0,0 +1,318 @@
+use std::borrow::Cow;
+
+use super::layout;
+
+// Longest tag worth looking for a closing '>' in, e.g. <C-S-Backspace>
+static MAX_TAG_LEN: usize = 24;
+
+// Tag names that aren't the name of a key in the layout file
+static KEY_ALIASES: [(&str, &str); 6] = [
+  ("bs", "backspace"),
+  ("cr", "enter"),
+  ("return", "enter"),
+  ("esc", "escape"),
+  ("del", "delete"),
+  ("capslock", "caps"),
+];
+
+// Prefixes for modifiers held in a tag like <C-w>
+static TAG_MODIFIERS: [(&str, &str); 5] = [("c", "ctrl"), ("s", "shift"), ("a", "alt"), ("m", "alt"), ("d", "gui")];
+
+// One thing to type: a char of the text, a <Tag>, or a chord
+pub struct Stroke<'a> {
+  pub ch: Option<char>,   // None for tags and chords of several chars
+  pub idx: usize,         // Byte offset in the text
+  pub chars: u32,         // # of chars of the text it types
+  pub combos: Option<Cow<'a, [layout::Combo<'a>]>>, // None if the layout can't type it
+}
+
+// Splits text into strokes. Text between < and > is read as a key
+// name with optional modifiers (<BS>, <Enter>, <C-w>, <C-S-Tab>), and
+// <lt> types a literal '<'. Anything else inside brackets is typed as
+// plain text.
+// With a chorded layout the longest chord matching the text is used,
+// as long as it doesn't start or end in the middle of a word. Spaces
+// the layout has no key for are inserted automatically, like steno
+// software does
+pub struct Strokes<'a, 's> {
+  text: &'s str,
+  idx: usize,
+  lay: &'a layout::Layout<'a>,
+  max_chord: usize, // Byte length of the longest chord
+}
+
+pub fn strokes<'a, 's>(text: &'s str, lay: &'a layout::Layout<'a>) -> Strokes<'a, 's> {
+  let max_chord = lay.chords.keys().map(|s| s.len()).max().unwrap_or(0);
+  Strokes {
+    text,
+    idx: 0,
+    lay,
+    max_chord,
+  }
+}
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '\''
+}
+
+impl<'a, 's> Iterator for Strokes<'a, 's> {
+  type Item = Stroke<'a>;
+
+  fn next(&mut self) -> Option<Stroke<'a>> {
+    let rest = &self.text[self.idx..];
+    let c = rest.chars().next()?;
+    let idx = self.idx;
+
+    if c == '<' {
+      if let Some(end) = rest.bytes().take(MAX_TAG_LEN).position(|b| b == b'>') {
+        let tag = &rest[1..end];
+        if tag.eq_ignore_ascii_case("lt") {
+          self.idx += end + 1;
+          return Some(char_stroke('<', idx, self.lay));
+        }
+
+        if let Some(combo) = parse_tag(tag, self.lay) {
+          self.idx += end + 1;
+          return Some(Stroke {
+            ch: None,
+            idx,
+            chars: 1,
+            combos: combo.map(|c| Cow::Owned(vec![c])),
+          });
+        }
+      }
+    }
+
+    if self.max_chord > 0 {
+      if let Some(stroke) = self.chord_stroke() {
+        return Some(stroke);
+      }
+    }
+
+    self.idx += c.len_utf8();
+    Some(char_stroke(c, idx, self.lay))
+  }
+}
+
+impl<'a, 's> Strokes<'a, 's> {
+  // The longest chord of more than one char at the start of the rest
+  // of the text
+  fn chord_stroke(&mut self) -> Option<Stroke<'a>> {
+    let rest = &self.text[self.idx..];
+    let after_word = self.text[..self.idx].chars().next_back().is_some_and(is_word_char);
+
+    let mut ends: Vec<usize> = rest.char_indices().skip(2).map(|(i, _)| i).take_while(|i| *i <= self.max_chord).collect();
+    if rest.len() <= self.max_chord && rest.chars().count() > 1 {
+      ends.push(rest.len());
+    }
+
+    for end in ends.into_iter().rev() {
+      let s = &rest[..end];
+      let first = s.chars().next()?;
+      let last = s.chars().next_back()?;
+      let next = rest[end..].chars().next();
+      if (is_word_char(first) && after_word) || (is_word_char(last) && next.is_some_and(is_word_char)) {
+        continue;
+      }
+
+      if let Some(combo) = self.lay.chord_for(s) {
+        let idx = self.idx;
+        self.idx += end;
+        return Some(Stroke {
+          ch: None,
+          idx,
+          chars: s.chars().count() as u32,
+          combos: Some(Cow::Owned(vec![combo])),
+        });
+      }
+    }
+
+    None
+  }
+}
+
+fn char_stroke<'a>(c: char, idx: usize, lay: &'a layout::Layout<'a>) -> Stroke<'a> {
+  let combos = match lay.combos_for(c) {
+    Some(cs) => Some(Cow::Borrowed(cs)),
+    None if lay.chords.is_empty() => None,
+    None => match lay.chord_for(c.encode_utf8(&mut [0; 4])) {
+      Some(combo) => Some(Cow::Owned(vec![combo])),
+      // Chorded layouts space words out on their own
+      None if c == ' ' => Some(Cow::Owned(Vec::new())),
+      None => None,
+    },
+  };
+
+  Stroke {
+    ch: Some(c),
+    idx,
+    chars: 1,
+    combos,
+  }
+}
+
+// Turn the inside of a tag into the combo it presses. A tag is only
+// valid if it has a modifier or names a key, so "<a>" stays literal.
+// Some(None) if it's valid but the layout can't press it, like <C-w>
+// without a ctrl key
+fn parse_tag<'a>(tag: &str, lay: &'a layout::Layout<'a>) -> Option<Option<layout::Combo<'a>>> {
+  if !tag.is_ascii() || tag.is_empty() {
+    return None;
+  }
+
+  let mut parts: Vec<&str> = tag.split('-').collect();
+  let mut name = parts.pop()?;
+  // Allow <C-->, with the key being '-'
+  if name.is_empty() && parts.last() == Some(&"") {
+    parts.pop();
+    name = "-";
+  }
+
+  let mut mod_kinds = Vec::new();
+  for part in parts {
+    let lower = part.to_ascii_lowercase();
+    let (_, kind) = TAG_MODIFIERS.iter().find(|(prefix, _)| *prefix == lower)?;
+    mod_kinds.push(*kind);
+  }
+
+  let mut combo = if name.chars().count() == 1 {
+    if mod_kinds.is_empty() {
+      return None;
+    }
+    match lay.char_keys.get(&name.chars().next()?) {
+      Some(c) => c.clone(),
+      None => return Some(None),
+    }
+  } else {
+    let lower = name.to_ascii_lowercase();
+    let alias = match KEY_ALIASES.iter().find(|(a, _)| *a == lower) {
+      Some((_, full)) => *full,
+      None => lower.as_str(),
+    };
+    match lay.key_by_name(alias) {
+      Some(key) => layout::Combo { key, mods: None },
+      None if mod_kinds.is_empty() => return None,
+      None => return Some(None),
+    }
+  };
+
+  if !mod_kinds.is_empty() {
+    let mut mods = combo.mods.take().unwrap_or_default();
+    for kind in mod_kinds {
+      let modifier = match lay.modifier_for(kind, combo.key.finger) {
+        Some(m) => m,
+        None => return Some(None),
+      };
+      // Shifted chars like <C-P> already hold shift
+      if !mods.iter().any(|m| std::ptr::eq(*m, modifier)) {
+        mods.push(modifier);
+      }
+    }
+    combo.mods = Some(mods);
+  }
+
+  Some(Some(combo))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::{analyze, model};
+  use super::*;
+
+  fn names(stroke: &Stroke) -> Vec<String> {
+    let combos = stroke.combos.as_ref().unwrap();
+    assert_eq!(combos.len(), 1);
+    let mut res = vec![combos[0].key.visual.name.clone()];
+    if let Some(mods) = &combos[0].mods {
+      res.extend(mods.iter().map(|m| m.visual.name.clone()));
+    }
+    res
+  }
+
+  #[test]
+  fn tags() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    let parsed: Vec<Stroke> = strokes("a<BS>b<C-w><enter><C-S-Tab>", lay).collect();
+    assert_eq!(parsed.len(), 6);
+
+    assert_eq!(parsed[0].ch, Some('a'));
+    assert_eq!(names(&parsed[1]), vec!["backspace"]);
+    assert_eq!(parsed[1].ch, None);
+    assert_eq!(parsed[1].idx, 1);
+    assert_eq!(parsed[2].ch, Some('b'));
+    assert_eq!(names(&parsed[3]), vec!["w", "rctrl"]);
+    assert_eq!(names(&parsed[4]), vec!["enter"]);
+    assert_eq!(names(&parsed[5]), vec!["tab", "rctrl", "rshift"]);
+  }
+
+  #[test]
+  fn missing_modifier() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/mobile-qwerty.layout").unwrap();
+    assert!(lay.modifier_for("ctrl", 0).is_none());
+
+    // A tag the layout has no ctrl for is one stroke it can't type,
+    // rather than literal text
+    let parsed: Vec<Stroke> = strokes("a<C-w>b", lay).collect();
+    assert_eq!(parsed.len(), 3);
+    assert_eq!((parsed[1].ch, parsed[1].idx), (None, 1));
+    assert!(parsed[1].combos.is_none());
+
+    let tl = analyze::gen_timeline_model("a<C-w>b", false, lay, &model::Model::default());
+    assert_eq!((tl.total_chars, tl.total_unmapped), (2, 1));
+  }
+
+  #[test]
+  fn literal_brackets() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    // Not tags, so each char is typed
+    let text = "if a<b> c <x> <lt>";
+    let parsed: Vec<Stroke> = strokes(text, lay).collect();
+    assert_eq!(parsed.len(), text.len() - 3);
+    assert!(parsed.iter().all(|s| s.ch.is_some() && s.combos.is_some()));
+    assert_eq!(parsed.last().unwrap().ch, Some('<'));
+
+    // Shifted chars keep their shift
+    let parsed: Vec<Stroke> = strokes("<C-P>", lay).collect();
+    assert_eq!(names(&parsed[0]), vec!["p", "lshift", "lctrl"]);
+  }
+
+  #[test]
+  fn chords() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/chorded.layout").unwrap();
+
+    let parsed: Vec<Stroke> = strokes("The cat bathed, to", lay).collect();
+    let chars: Vec<u32> = parsed.iter().map(|s| s.chars).collect();
+    // "the" inside "bathed" isn't a word, so it's typed letter by letter
+    assert_eq!(chars, vec![3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2]);
+    assert_eq!(parsed[0].ch, None);
+    assert_eq!(names(&parsed[2]), vec!["s", "t"]);
+    assert_eq!(names(&parsed[3]), vec!["a"]);
+    assert!(parsed.iter().all(|s| s.combos.is_some()));
+
+    // Spaces without a key are free
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "test/chords.layout").unwrap();
+    let parsed: Vec<Stroke> = strokes("ab bc", lay).collect();
+    assert_eq!(parsed.len(), 3);
+    assert!(parsed[1].combos.as_ref().unwrap().is_empty());
+    assert_eq!(parsed[2].chars, 2);
+    assert_eq!(strokes("abc", lay).count(), 3);
+  }
+
+  #[test]
+  fn control_chars() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    let parsed: Vec<Stroke> = strokes("a\n\tb", lay).collect();
+    assert_eq!(names(&parsed[1]), vec!["enter"]);
+    assert_eq!(names(&parsed[2]), vec!["tab"]);
+    assert!(strokes("\r", lay).next().unwrap().combos.is_none());
+  }
+}
@@ -0,0 +1,219 @@
+use super::layout;
+
+// evdev names of keys whose layout name isn't just their lowercased
+// name without KEY_
+static EVDEV_NAMES: [(&str, &str); 21] = [
+  ("leftshift", "lshift"),
+  ("rightshift", "rshift"),
+  ("leftctrl", "lctrl"),
+  ("rightctrl", "rctrl"),
+  ("leftalt", "lalt"),
+  ("rightalt", "ralt"),
+  ("leftmeta", "lgui"),
+  ("rightmeta", "rgui"),
+  ("capslock", "caps"),
+  ("esc", "escape"),
+  ("grave", "`"),
+  ("minus", "-"),
+  ("equal", "="),
+  ("leftbrace", "["),
+  ("rightbrace", "]"),
+  ("backslash", "\\"),
+  ("semicolon", ";"),
+  ("apostrophe", "'"),
+  ("comma", ","),
+  ("dot", "."),
+  ("slash", "/"),
+];
+
+// A key held down in a recorded session
+pub struct KeyPress<'a> {
+  pub key: &'a layout::Key,
+  pub down: i32, // ms from the first event of the log
+  pub up: i32,
+  pub ch: Option<char>, // What it typed, None for modifiers and named keys
+  pub modifier: bool,
+}
+
+// The key of the layout a log names: a key name, a char the layout
+// types, or an evdev name like KEY_A or KEY_LEFTSHIFT
+pub fn find_key<'a>(lay: &'a layout::Layout, name: &str) -> Option<&'a layout::Key> {
+  if let Some(evdev) = name.strip_prefix("KEY_") {
+    let lower = evdev.to_lowercase();
+    let name = match EVDEV_NAMES.iter().find(|(e, _)| *e == lower) {
+      Some((_, n)) => n,
+      None => lower.as_str(),
+    };
+    return lay.key_by_name(name);
+  }
+
+  if let Some(key) = lay.key_by_name(name) {
+    return Some(key);
+  }
+  let mut chars = name.chars();
+  match (chars.next(), chars.next()) {
+    (Some(c), None) => lay.char_keys.get(&c).map(|co| co.key),
+    _ => None,
+  }
+}
+
+fn is_modifier(lay: &layout::Layout, key: &layout::Key) -> bool {
+  lay.mod_map.values().any(|m| std::ptr::eq(m, key))
+}
+
+// Logs are CSV files with a time_ms,event,key header, where event is
+// down or up (or evdev's 1 and 0, repeats are 2 and are skipped).
+// Times are in ms from any starting point. Keys the layout doesn't
+// have are reported and skipped
+pub fn read_log<'a>(path: &str, lay: &'a layout::Layout) -> Option<Vec<KeyPress<'a>>> {
+  let mut reader = match csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(path) {
+    Ok(r) => r,
+    Err(e) => {
+      println!("{}", e);
+      return None;
+    }
+  };
+
+  let mut presses: Vec<KeyPress> = Vec::new();
+  let mut held: Vec<usize> = Vec::new(); // Indices of presses not let go of yet
+  let mut start = None;
+  let mut unknown: Vec<String> = Vec::new();
+
+  for (line, res) in reader.records().enumerate() {
+    let record = match res {
+      Ok(r) => r,
+      Err(e) => {
+        println!("{}", e);
+        return None;
+      }
+    };
+
+    let time = match record.get(0).and_then(|t| t.parse::<f64>().ok()) {
+      Some(t) => t,
+      None => {
+        println!("Line {} of {} needs a time", line + 2, path);
+        return None;
+      }
+    };
+    let time = (time - *start.get_or_insert(time)).round() as i32;
+
+    let name = record.get(2).unwrap_or("");
+    let key = match find_key(lay, name) {
+      Some(k) => k,
+      None => {
+        if !unknown.iter().any(|u| u == name) {
+          unknown.push(name.to_string());
+        }
+        continue;
+      }
+    };
+    let held_idx = held.iter().position(|i| std::ptr::eq(presses[*i].key, key));
+
+    match record.get(1).unwrap_or("") {
+      "down" | "1" => {
+        if held_idx.is_some() {
+          continue;
+        }
+        held.push(presses.len());
+        presses.push(KeyPress {
+          key,
+          down: time,
+          up: time,
+          ch: None,
+          modifier: is_modifier(lay, key),
+        });
+      }
+      "up" | "0" => {
+        if let Some(h) = held_idx {
+          presses[held.remove(h)].up = time;
+        }
+      }
+      "2" => continue,
+      event => {
+        println!("Line {} of {}: unknown event {}", line + 2, path, event);
+        return None;
+      }
+    }
+  }
+
+  if !unknown.is_empty() {
+    println!("Skipped keys the layout doesn't have: {}", unknown.join(" "));
+  }
+
+  // Keys still held at the end are let go of with the last event
+  let end = presses.iter().map(|p| p.up.max(p.down)).max().unwrap_or(0);
+  for i in held {
+    presses[i].up = end;
+  }
+
+  // What each press typed, given the shifts held at the time
+  for i in 0..presses.len() {
+    let p = &presses[i];
+    if p.modifier {
+      continue;
+    }
+    let shifted = presses.iter().any(|m| {
+      m.modifier && m.key.visual.name.ends_with("shift") && m.down <= p.down && m.up >= p.down
+    });
+    let c = if shifted && p.key.shifted != '\0' { p.key.shifted } else { p.key.pressed };
+    presses[i].ch = match c {
+      '\0' => None,
+      c => Some(c),
+    };
+  }
+
+  Some(presses)
+}
+
+// The text a session typed, with backspaces applied
+pub fn typed_text(presses: &[KeyPress]) -> String {
+  let mut text = String::new();
+  for p in presses {
+    match (p.ch, p.key.visual.name.as_str()) {
+      (Some(c), _) => text.push(c),
+      (None, "enter") => text.push('\n'),
+      (None, "tab") => text.push('\t'),
+      (None, "backspace") => {
+        text.pop();
+      }
+      _ => {}
+    }
+  }
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn read() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    let presses = read_log("test/session.log", lay).unwrap();
+    let names: Vec<&str> = presses.iter().map(|p| p.key.visual.name.as_str()).collect();
+    assert_eq!(names, vec!["lshift", "h", "i", "space", "x", "backspace", "a", "enter"]);
+    assert_eq!((presses[0].down, presses[0].up), (0, 180));
+    assert!(presses[0].modifier);
+    assert_eq!(presses[1].ch, Some('H'));
+    assert_eq!(presses[2].ch, Some('i'));
+    // Held to the end
+    assert_eq!(presses[7].up, presses[7].down);
+
+    assert_eq!(typed_text(&presses), "Hi a\n");
+  }
+
+  #[test]
+  fn key_names() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    assert_eq!(find_key(lay, "KEY_A").unwrap().visual.name, "a");
+    assert_eq!(find_key(lay, "KEY_LEFTSHIFT").unwrap().visual.name, "lshift");
+    assert_eq!(find_key(lay, "KEY_SEMICOLON").unwrap().visual.name, ";");
+    assert_eq!(find_key(lay, "A").unwrap().visual.name, "a");
+    assert_eq!(find_key(lay, "space").unwrap().pressed, ' ');
+    assert!(find_key(lay, "KEY_F13").is_none());
+  }
+}
@@ -3,12 +3,41 @@ use std::collections::HashMap;
 pub struct Key {
   pub pressed: char,
   pub shifted: char,
+  pub levels: Vec<char>, // AltGr and AltGr+shift, '\0' if unused
   pub finger: i16,
   pub is_home: bool,
   pub pos: Pos,
   pub visual: VisKey,
+  pub line: usize, // In the layout or board file, 0 if not from one
 }
 
+impl Key {
+  pub fn center(&self) -> Pos {
+    Pos {
+      x: self.pos.x + self.visual.width / 2.0,
+      y: self.pos.y + self.visual.height / 2.0,
+    }
+  }
+
+  // Clockwise from the top left before rotating
+  pub fn corners(&self) -> [Pos; 4] {
+    let center = self.center();
+    let (w, h) = (self.visual.width / 2.0, self.visual.height / 2.0);
+    let mut res = [Pos::default(); 4];
+    for (corner, (dx, dy)) in res.iter_mut().zip([(-w, -h), (w, -h), (w, h), (-w, h)].iter()) {
+      let p = Pos {
+        x: center.x + dx,
+        y: center.y + dy,
+      };
+      *corner = rotate(p, center, self.visual.rotation);
+    }
+    res
+  }
+}
+
+// For rotated keys this is where the top left would be if the key
+// was rotated back around its center, so pos + (width / 2, height / 2)
+// is always the real center
 #[derive(Default, Copy, Clone)]
 pub struct Pos {
   pub x: f32,
@@ -19,9 +48,38 @@ pub struct Pos {
 pub struct VisKey {
   pub width: f32,
   pub height: f32,
+  pub rotation: f32, // degrees clockwise around the key's center
   pub name: String,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Hand {
+  Left,
+  Right,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FingerKind {
+  Thumb,
+  Index,
+  Middle,
+  Ring,
+  Pinky,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Finger {
+  pub hand: Hand,
+  pub kind: FingerKind,
+}
+
+impl Finger {
+  pub fn is_thumb(&self) -> bool {
+    self.kind == FingerKind::Thumb
+  }
+}
+
+#[derive(Clone)]
 pub struct Combo<'a> {
   pub key: &'a Key,
   pub mods: Option<Vec<&'a Key>>,
@@ -30,7 +88,10 @@ pub struct Combo<'a> {
 pub struct Layout<'a> {
   pub keys: Vec<Key>, // Stores text-inputting keys
   pub char_keys: HashMap<char, Combo<'a>>,
+  pub sequences: HashMap<char, Vec<Combo<'a>>>, // Chars typed with several presses
+  pub chords: HashMap<String, Vec<&'a Key>>, // Keys pressed together to type a string
   pub homes: Vec<&'a Key>,
+  pub fingers: Vec<Finger>, // Same indexes as homes
   pub mod_map: HashMap<String, Key>, // Stores modifiers
 }
 
@@ -39,7 +100,10 @@ impl<'a> Default for Layout<'a> {
     Self {
       keys: Vec::new(),
       char_keys: HashMap::new(),
+      sequences: HashMap::new(),
+      chords: HashMap::new(),
       homes: vec![&DUMMY_KEY; 1],
+      fingers: Vec::new(),
       mod_map: HashMap::new(),
     }
   }
@@ -48,22 +112,254 @@ impl<'a> Default for Layout<'a> {
 pub static DUMMY_KEY: Key = Key {
   pressed: '\0',
   shifted: '\0',
+  levels: Vec::new(),
   finger: -1,
   is_home: false,
   pos: Pos { x: -2.0, y: -2.0 },
   visual: VisKey {
     width: 0.0,
     height: 0.0,
+    rotation: 0.0,
     name: String::new(),
   },
+  line: 0,
 };
 
-static MODIFIERS: [&str; 3] = ["lshift", "rshift", "shift"];
+static MODIFIERS: [&str; 13] = [
+  "lshift", "rshift", "shift", "lctrl", "rctrl", "ctrl", "lalt", "ralt", "alt", "lgui", "rgui", "gui", "altgr",
+];
+
+// Chars on the third and fourth levels are typed holding the first
+// of these that the layout has
+static ALTGR_KEYS: [&str; 2] = ["altgr", "ralt"];
+
+// Control chars in text are typed with these named keys
+static CONTROL_CHARS: [(char, &str); 3] = [('\n', "enter"), ('\t', "tab"), ('\u{8}', "backspace")];
+
+// Rows with this name declare a finger instead of a key, as
+// finger,<left/right>,<thumb/index/middle/ring/pinky>,<index>,<home key name>
+// Fingers without one are guessed from their index
+static FINGER_ROW: &str = "finger";
+
+// Rows with this name move every key after them by their x and y, so
+// each half of a split board can be written in its own coordinates
+static OFFSET_ROW: &str = "offset";
+
+// Rows with this name describe a char typed with a sequence of
+// presses (dead keys, compose) instead of a physical key
+static SEQUENCE_ROW: &str = "sequence";
+
+// Rows with this name describe a chord, a set of keys pressed at once
+// that types the string in the unshifted column, as
+// chord,<string>,<space separated key names>
+static CHORD_ROW: &str = "chord";
+
+impl<'a> Layout<'a> {
+  // The presses needed to type c. Sequences take priority so a dead
+  // key's own char can be defined as the dead key followed by space
+  pub fn combos_for(&self, c: char) -> Option<&[Combo<'a>]> {
+    if let Some(seq) = self.sequences.get(&c) {
+      return Some(seq.as_slice());
+    }
+    self.char_keys.get(&c).map(std::slice::from_ref)
+  }
+
+  // The chord that types s as one combo. A capitalized s can use the
+  // chord for the lowercase one with shift held
+  pub fn chord_for(&self, s: &str) -> Option<Combo<'_>> {
+    if let Some(keys) = self.chords.get(s) {
+      return Some(chord_combo(keys));
+    }
+
+    let mut chars = s.chars();
+    let first = chars.next().filter(|c| c.is_uppercase())?;
+    let lower: String = first.to_lowercase().chain(chars).collect();
+    let mut combo = chord_combo(self.chords.get(&lower)?);
+    let shift = self.modifier_for("shift", combo.key.finger)?;
+    combo.mods.get_or_insert_with(Vec::new).push(shift);
+    Some(combo)
+  }
+
+  pub fn key_by_name(&self, name: &str) -> Option<&Key> {
+    find_key(&self.keys, &self.mod_map, name)
+  }
+
+  // The modifier key of a kind ("shift", "ctrl", ...) to hold while
+  // finger presses a key. None if the layout doesn't have one
+  pub fn modifier_for(&self, kind: &str, finger: i16) -> Option<&Key> {
+    pick_modifier(&self.mod_map, &self.fingers, kind, finger)
+  }
+}
+
+// Use the modifier on the opposite hand, falling back to a lone
+// unsided one
+fn pick_modifier<'a>(mod_map: &'a HashMap<String, Key>, fingers: &[Finger], kind: &str, finger: i16) -> Option<&'a Key> {
+  let left = finger >= 0 && fingers.get(finger as usize).is_some_and(|f| f.hand == Hand::Left);
+  let side = if left { "r" } else { "l" };
+
+  mod_map.get(&format!("{}{}", side, kind)).or_else(|| mod_map.get(kind))
+}
+
+// Chords are checked on load to have one key per finger
+fn chord_combo<'a>(keys: &[&'a Key]) -> Combo<'a> {
+  let mods = keys[1..].to_vec();
+  Combo {
+    key: keys[0],
+    mods: if mods.is_empty() { None } else { Some(mods) },
+  }
+}
+
+fn find_key<'a>(keys: &'a [Key], mod_map: &'a HashMap<String, Key>, name: &str) -> Option<&'a Key> {
+  match mod_map.get(name) {
+    Some(k) => Some(k),
+    None => keys.iter().find(|k| k.visual.name == name),
+  }
+}
+
+fn parse_hand(s: &str) -> Option<Hand> {
+  match s.to_ascii_lowercase().as_str() {
+    "l" | "left" => Some(Hand::Left),
+    "r" | "right" => Some(Hand::Right),
+    _ => None,
+  }
+}
+
+fn parse_finger_kind(s: &str) -> Option<FingerKind> {
+  match s.to_ascii_lowercase().as_str() {
+    "thumb" => Some(FingerKind::Thumb),
+    "index" => Some(FingerKind::Index),
+    "middle" => Some(FingerKind::Middle),
+    "ring" => Some(FingerKind::Ring),
+    "pinky" => Some(FingerKind::Pinky),
+    _ => None,
+  }
+}
+
+// Guess what each finger is for layouts that don't say. Fingers are
+// numbered left to right, split in half between the hands. A finger
+// homed on space is a thumb on whichever side it's on, and a hand
+// with 5 fingers has a thumb as its innermost one
+fn infer_fingers(homes: &[&Key], space: Option<&Key>) -> Vec<Finger> {
+  let num = homes.len();
+  let space_finger = space.filter(|k| k.is_home).map(|k| k.finger);
+
+  let mut res = Vec::new();
+  for i in 0..num {
+    let hand = if i < num / 2 || (num % 2 == 1 && i == num / 2 && space_finger == Some(i as i16)) {
+      Hand::Left
+    } else {
+      Hand::Right
+    };
+    res.push(Finger {
+      hand,
+      kind: FingerKind::Index,
+    });
+  }
+
+  for hand in [Hand::Left, Hand::Right].iter() {
+    // From the middle of the board outwards
+    let mut inner_to_outer: Vec<usize> = (0..num).filter(|i| res[*i].hand == *hand).collect();
+    if *hand == Hand::Left {
+      inner_to_outer.reverse();
+    }
+
+    let has_space = inner_to_outer.iter().any(|i| space_finger == Some(*i as i16));
+    let mut kinds = vec![FingerKind::Index, FingerKind::Middle, FingerKind::Ring, FingerKind::Pinky].into_iter();
+    for (n, i) in inner_to_outer.iter().enumerate() {
+      res[*i].kind = if space_finger == Some(*i as i16) || (n == 0 && !has_space && inner_to_outer.len() == 5) {
+        FingerKind::Thumb
+      } else {
+        kinds.next().unwrap_or(FingerKind::Pinky)
+      };
+    }
+  }
+
+  res
+}
+
+// Rotate p by degrees clockwise around origin
+fn rotate(p: Pos, origin: Pos, degrees: f32) -> Pos {
+  let (sin, cos) = degrees.to_radians().sin_cos();
+  let x = p.x - origin.x;
+  let y = p.y - origin.y;
+
+  Pos {
+    x: origin.x + x * cos - y * sin,
+    y: origin.y + x * sin + y * cos,
+  }
+}
+
+// The chars a mapping file puts on each key of a board
+struct Mapping {
+  chars: HashMap<String, (char, char, Vec<char>)>, // (unshifted, shifted, levels) by key name
+  sequences: Vec<(char, String)>,
+  chords: Vec<(String, String, usize)>,
+}
+
+// Mappings are CSV files with a header and a row per key:
+// name,unshifted,shifted,altgr,altgr_shifted
+// plus sequence rows like in a layout
+fn read_mapping(path: &str) -> Option<Mapping> {
+  let mut reader;
+  match csv::ReaderBuilder::new().flexible(true).from_path(path) {
+    Ok(r) => reader = r,
+    Err(e) => panic!("{}", e),
+  }
+
+  let mut mapping = Mapping {
+    chars: HashMap::new(),
+    sequences: Vec::new(),
+    chords: Vec::new(),
+  };
+  for res in reader.records() {
+    let record = match res {
+      Ok(r) => r,
+      Err(e) => panic!("{}", e),
+    };
+
+    let name = record.get(0)?.to_string();
+    let col = |i| record.get(i).and_then(|s| s.chars().next()).unwrap_or('\0');
+    if name == SEQUENCE_ROW {
+      if col(1) != '\0' {
+        mapping.sequences.push((col(1), record.get(2)?.to_string()));
+      }
+      continue;
+    }
+    if name == CHORD_ROW {
+      let line = record.position().map_or(0, |p| p.line() as usize);
+      mapping.chords.push((record.get(1)?.to_string(), record.get(2)?.to_string(), line));
+      continue;
+    }
+
+    let mut levels = vec![col(3), col(4)];
+    while levels.last() == Some(&'\0') {
+      levels.pop();
+    }
+    mapping.chars.insert(name, (col(1), col(2), levels));
+  }
+
+  Some(mapping)
+}
 
 // Fill lay with the layout info from path
+// Besides the usual columns, keys can have rotation, rx and ry
+// columns, which rotate them by that many degrees clockwise around
+// (rx, ry) like KLE does. Like y, these carry over to the next rows
 pub fn init<'a>(lay: &'a mut Layout<'a>, path: &str) -> Option<&'a Layout<'a>> {
+  load(lay, path, None)
+}
+
+// Fill lay with the keys of a board file, which is a layout file
+// that usually leaves the char columns empty, and the chars the
+// mapping file at map_path puts on them by key name
+pub fn init_mapped<'a>(lay: &'a mut Layout<'a>, board_path: &str, map_path: &str) -> Option<&'a Layout<'a>> {
+  let mapping = read_mapping(map_path)?;
+  load(lay, board_path, Some(mapping))
+}
+
+fn load<'a>(lay: &'a mut Layout<'a>, path: &str, mut mapping: Option<Mapping>) -> Option<&'a Layout<'a>> {
   let mut reader;
-  match csv::ReaderBuilder::new().from_path(path) {
+  match csv::ReaderBuilder::new().flexible(true).from_path(path) {
     Ok(r) => reader = r,
     Err(e) => panic!("{}", e),
   }
@@ -71,6 +367,16 @@ pub fn init<'a>(lay: &'a mut Layout<'a>, path: &str) -> Option<&'a Layout<'a>> {
   let mut prev_x = 0.0;
   let mut prev_y = 0.0;
   let mut prev_w = 0.0;
+  let mut prev_rot = 0.0;
+  let mut prev_origin = Pos::default();
+  let mut offset = Pos::default();
+
+  // (char, space separated chars or key names) for each sequence row
+  let mut seq_rows = Vec::new();
+  // (string, space separated key names, line) for each chord row
+  let mut chord_rows = Vec::new();
+  // (finger index, finger, home key name) for each finger row
+  let mut finger_rows = Vec::new();
 
   for res in reader.records() {
     let record = match res {
@@ -79,8 +385,49 @@ pub fn init<'a>(lay: &'a mut Layout<'a>, path: &str) -> Option<&'a Layout<'a>> {
     };
 
     let name = record.get(0)?.to_string();
-    let pressed = record.get(1)?.chars().next().unwrap_or('\0');
-    let shifted = record.get(2)?.chars().next().unwrap_or('\0');
+    if name == SEQUENCE_ROW {
+      if let Some(c) = record.get(1)?.chars().next() {
+        seq_rows.push((c, record.get(2)?.to_string()));
+      }
+      continue;
+    }
+    if name == CHORD_ROW {
+      let line = record.position().map_or(0, |p| p.line() as usize);
+      chord_rows.push((record.get(1)?.to_string(), record.get(2)?.to_string(), line));
+      continue;
+    }
+    if name == FINGER_ROW {
+      let finger = match (
+        parse_hand(record.get(1)?),
+        parse_finger_kind(record.get(2)?),
+        record.get(3)?.parse::<usize>(),
+      ) {
+        (Some(hand), Some(kind), Ok(i)) => (i, Finger { hand, kind }),
+        _ => {
+          println!("Couldn't read finger row: {:?}", record);
+          return None;
+        }
+      };
+      while lay.homes.len() <= finger.0 {
+        lay.homes.push(&DUMMY_KEY);
+      }
+      finger_rows.push((finger.0, finger.1, record.get(4)?.to_string()));
+      continue;
+    }
+    if name == OFFSET_ROW {
+      offset.x = record.get(5)?.parse::<f32>().unwrap_or(0.0);
+      offset.y = record.get(6)?.parse::<f32>().unwrap_or(0.0);
+      continue;
+    }
+
+    let (pressed, shifted, levels) = match mapping.as_mut().and_then(|m| m.chars.remove(&name)) {
+      Some(chars) => chars,
+      None => (
+        record.get(1)?.chars().next().unwrap_or('\0'),
+        record.get(2)?.chars().next().unwrap_or('\0'),
+        Vec::new(),
+      ),
+    };
 
     let finger = record.get(3)?.parse::<i16>().unwrap_or(-1);
     while (lay.homes.len() as i16) <= finger {
@@ -95,21 +442,37 @@ pub fn init<'a>(lay: &'a mut Layout<'a>, path: &str) -> Option<&'a Layout<'a>> {
     let w = record.get(7)?.parse::<f32>().unwrap_or(1.0);
     let h = record.get(8)?.parse::<f32>().unwrap_or(1.0);
 
+    let rotation = record.get(9).unwrap_or("").parse::<f32>().unwrap_or(prev_rot);
+    let origin = Pos {
+      x: record.get(10).unwrap_or("").parse::<f32>().unwrap_or(prev_origin.x),
+      y: record.get(11).unwrap_or("").parse::<f32>().unwrap_or(prev_origin.y),
+    };
+
     prev_x = x;
     prev_y = y;
     prev_w = w;
+    prev_rot = rotation;
+    prev_origin = origin;
+
+    let center = rotate(Pos { x: x + w / 2.0, y: y + h / 2.0 }, origin, rotation);
 
     let key = Key {
       pressed,
       shifted,
+      levels,
       finger,
       is_home,
-      pos: Pos { x, y },
+      pos: Pos {
+        x: center.x - w / 2.0 + offset.x,
+        y: center.y - h / 2.0 + offset.y,
+      },
       visual: VisKey {
         width: w,
         height: h,
+        rotation,
         name,
       },
+      line: record.position().map_or(0, |p| p.line() as usize),
     };
 
     if MODIFIERS.contains(&key.visual.name.as_str()) {
@@ -119,24 +482,45 @@ pub fn init<'a>(lay: &'a mut Layout<'a>, path: &str) -> Option<&'a Layout<'a>> {
     }
   }
 
+  if let Some(m) = mapping {
+    let mut missing: Vec<&String> = m.chars.keys().collect();
+    missing.sort();
+    for name in missing {
+      println!("Mapping puts chars on key {}, which isn't on the board", name);
+    }
+    seq_rows.extend(m.sequences);
+    chord_rows.extend(m.chords);
+  }
+
   for key in &lay.keys {
-    if key.pressed != '\0' {
-      lay.char_keys.insert(key.pressed, Combo { key, mods: None });
+    if key.is_home && key.finger >= 0 {
+      lay.homes[key.finger as usize] = key;
     }
-    if key.shifted != '\0' {
-      let mut mods = Vec::new();
+  }
 
-      let mut shift_key = "lshift";
-      if (key.finger as usize) < (lay.homes.len() / 2) {
-        shift_key = "rshift";
+  // Modifiers are picked by hand, so fingers come before chars
+  let space = lay.keys.iter().rev().find(|k| k.pressed == ' ' || k.shifted == ' ');
+  lay.fingers = infer_fingers(&lay.homes, space);
+  for (i, finger, home) in finger_rows {
+    lay.fingers[i] = finger;
+    if home.is_empty() {
+      continue;
+    }
+    match find_key(&lay.keys, &lay.mod_map, &home) {
+      Some(k) => lay.homes[i] = k,
+      None => {
+        println!("Finger {} is homed on unknown key {}", i, home);
+        return None;
       }
+    }
+  }
 
-      let shift = match lay.mod_map.get(shift_key) {
-        Some(s) => s,
-        None => lay.mod_map.get("shift").unwrap_or(&DUMMY_KEY),
-      };
-
-      mods.push(shift);
+  for key in &lay.keys {
+    if key.pressed != '\0' {
+      lay.char_keys.insert(key.pressed, Combo { key, mods: None });
+    }
+    if key.shifted != '\0' {
+      let mods = vec![pick_modifier(&lay.mod_map, &lay.fingers, "shift", key.finger).unwrap_or(&DUMMY_KEY)];
 
       lay.char_keys.insert(
         key.shifted,
@@ -146,12 +530,82 @@ pub fn init<'a>(lay: &'a mut Layout<'a>, path: &str) -> Option<&'a Layout<'a>> {
         },
       );
     }
+  }
 
-    if key.is_home && key.finger >= 0 {
-      lay.homes[key.finger as usize] = key;
+  // Chars on the other levels never replace ones on the first two
+  let mod_map = &lay.mod_map;
+  if let Some(altgr) = ALTGR_KEYS.iter().find_map(|name| mod_map.get(*name)) {
+    for key in &lay.keys {
+      for (level, c) in key.levels.iter().enumerate() {
+        if *c == '\0' || lay.char_keys.contains_key(c) {
+          continue;
+        }
+        let mut mods = vec![altgr];
+        if level == 1 {
+          mods.push(pick_modifier(mod_map, &lay.fingers, "shift", key.finger).unwrap_or(&DUMMY_KEY));
+        }
+        lay.char_keys.insert(*c, Combo { key, mods: Some(mods) });
+      }
     }
   }
 
+  for (c, name) in CONTROL_CHARS.iter() {
+    if lay.char_keys.contains_key(c) {
+      continue;
+    }
+    if let Some(key) = lay.keys.iter().find(|k| k.visual.name == *name) {
+      lay.char_keys.insert(*c, Combo { key, mods: None });
+    }
+  }
+
+  for (c, tokens) in seq_rows {
+    let mut seq = Vec::new();
+    for token in tokens.split_whitespace() {
+      let mut token_chars = token.chars();
+      let combo = match (token_chars.next(), token_chars.next()) {
+        (Some(tc), None) => lay.char_keys.get(&tc).cloned(),
+        _ => find_key(&lay.keys, &lay.mod_map, token).map(|key| Combo { key, mods: None }),
+      };
+
+      match combo {
+        Some(co) => seq.push(co),
+        None => {
+          println!("Sequence for {} uses unknown key {}", c, token);
+          return None;
+        }
+      }
+    }
+    lay.sequences.insert(c, seq);
+  }
+
+  for (output, names, line) in chord_rows {
+    let mut keys = Vec::new();
+    for name in names.split_whitespace() {
+      match find_key(&lay.keys, &lay.mod_map, name) {
+        Some(k) => keys.push(k),
+        None => {
+          println!("Chord for {:?} uses unknown key {}", output, name);
+          return None;
+        }
+      }
+    }
+    if output.is_empty() || keys.is_empty() {
+      println!("Chord rows need a string and at least one key");
+      return None;
+    }
+    // A finger can only press one key at a time
+    for (i, key) in keys.iter().enumerate() {
+      if let Some(other) = keys[..i].iter().find(|k| k.finger == key.finger) {
+        println!(
+          "Chord for {:?} on line {} has keys {} and {} on finger {}, which can only press one",
+          output, line, other.visual.name, key.visual.name, key.finger
+        );
+        return None;
+      }
+    }
+    lay.chords.insert(output, keys);
+  }
+
   Some(lay)
 }
 
@@ -247,6 +701,196 @@ mod tests {
     assert_eq!(c.key.visual.height, 2.5);
   }
 
+  #[test]
+  fn test_sequences() {
+    let mut lay = Layout::default();
+
+    let lay = match init(&mut lay, "test/sequences.layout") {
+      Some(l) => l,
+      None => return,
+    };
+
+    // Dead key by name, then a char
+    let acute = lay.combos_for('é').unwrap();
+    assert_eq!(acute.len(), 2);
+    assert_eq!(acute[0].key.visual.name, "dead_acute");
+    assert!(acute[0].mods.is_none());
+    assert_eq!(acute[1].key.pressed, 'e');
+
+    // Shifted dead key
+    let diaeresis = lay.combos_for('ë').unwrap();
+    assert_eq!(diaeresis[0].key.visual.name, "dead_acute");
+    assert_eq!(diaeresis[0].mods.as_ref().unwrap()[0].visual.name, "rshift");
+
+    // Compose key
+    let tilde = lay.combos_for('ñ').unwrap();
+    assert_eq!(tilde.len(), 3);
+    assert_eq!(tilde[0].key.visual.name, "compose");
+
+    // Sequences override the dead key's own char
+    assert_eq!(lay.combos_for('´').unwrap().len(), 2);
+    assert_eq!(lay.combos_for('e').unwrap().len(), 1);
+    assert!(lay.combos_for('x').is_none());
+  }
+
+  #[test]
+  fn test_control_chars() {
+    let mut lay = Layout::default();
+    let lay = init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    assert_eq!(lay.char_keys.get(&'\n').unwrap().key.visual.name, "enter");
+    assert_eq!(lay.char_keys.get(&'\t').unwrap().key.visual.name, "tab");
+    assert!(lay.char_keys.get(&'\n').unwrap().mods.is_none());
+    assert_eq!(lay.modifier_for("ctrl", 0).unwrap().visual.name, "rctrl");
+    assert_eq!(lay.modifier_for("ctrl", 9).unwrap().visual.name, "lctrl");
+  }
+
+  #[test]
+  fn test_rotation() {
+    let mut lay = Layout::default();
+    let lay = init(&mut lay, "test/rotation.layout").unwrap();
+
+    let close = |a: f32, b: f32| (a - b).abs() < 0.0001;
+
+    // Rotated a quarter turn around its top left
+    let a = lay.char_keys.get(&'a').unwrap().key;
+    assert!(close(a.pos.x, -1.0));
+    assert!(close(a.pos.y, 0.0));
+    assert_eq!(a.visual.rotation, 90.0);
+
+    // Rotation carries over, and so does moving right in the
+    // unrotated row, which ends up moving down
+    let b = lay.char_keys.get(&'b').unwrap().key;
+    assert!(close(b.pos.x, -1.0));
+    assert!(close(b.pos.y, 1.0));
+
+    // Rotating around the center leaves pos alone
+    let c = lay.char_keys.get(&'c').unwrap().key;
+    assert!(close(c.pos.x, 4.0));
+    assert!(close(c.pos.y, 0.0));
+
+    // Offset keys
+    let d = lay.char_keys.get(&'d').unwrap().key;
+    assert!(close(d.pos.x, 10.0));
+    assert!(close(d.pos.y, 1.5));
+    assert_eq!(d.visual.rotation, 0.0);
+  }
+
+  #[test]
+  fn test_inferred_fingers() {
+    let mut lay = Layout::default();
+    let lay = init(&mut lay, "layouts/qwerty.layout").unwrap();
+
+    use FingerKind::*;
+    let kinds: Vec<FingerKind> = lay.fingers.iter().map(|f| f.kind).collect();
+    assert_eq!(kinds, vec![Pinky, Ring, Middle, Index, Thumb, Thumb, Index, Middle, Ring, Pinky]);
+    assert!(lay.fingers[..5].iter().all(|f| f.hand == Hand::Left));
+    assert!(lay.fingers[5..].iter().all(|f| f.hand == Hand::Right));
+  }
+
+  #[test]
+  fn test_declared_fingers() {
+    let mut lay = Layout::default();
+    let lay = init(&mut lay, "test/fingers.layout").unwrap();
+
+    assert_eq!(lay.fingers.len(), 4);
+    assert_eq!(lay.homes.len(), 4);
+
+    // Declared
+    assert_eq!(lay.fingers[0].hand, Hand::Left);
+    assert_eq!(lay.fingers[0].kind, FingerKind::Thumb);
+    assert_eq!(lay.homes[0].visual.name, "lspace");
+    assert_eq!(lay.fingers[3].hand, Hand::Right);
+    assert_eq!(lay.fingers[3].kind, FingerKind::Thumb);
+    assert_eq!(lay.homes[3].visual.name, "rspace");
+
+    // Guessed, with the home from the key
+    assert_eq!(lay.fingers[1].hand, Hand::Left);
+    assert_eq!(lay.fingers[1].kind, FingerKind::Index);
+    assert_eq!(lay.homes[1].visual.name, "f");
+    assert_eq!(lay.fingers[2].hand, Hand::Right);
+  }
+
+  #[test]
+  // Shift is on the other hand from the finger, however many there are
+  fn test_shift_hand() {
+    let mut lay = Layout::default();
+    let lay = init(&mut lay, "test/hands.layout").unwrap();
+
+    let shift = |c: char| lay.char_keys.get(&c).unwrap().mods.as_ref().unwrap()[0].visual.name.clone();
+    assert_eq!(shift('D'), "rshift");
+    assert_eq!(shift('J'), "lshift");
+    assert_eq!(lay.modifier_for("shift", 2).unwrap().visual.name, "rshift");
+  }
+
+  #[test]
+  fn test_mapped() {
+    let mut lay = Layout::default();
+    let lay = init_mapped(&mut lay, "boards/ansi.board", "mappings/dvorak.map").unwrap();
+
+    let e = lay.char_keys.get(&'e').unwrap().key;
+    assert_eq!(e.visual.name, "d");
+    assert_eq!(e.finger, 2);
+    assert!(e.is_home);
+    assert_eq!(lay.char_keys.get(&'"').unwrap().key.visual.name, "q");
+    assert_eq!(lay.char_keys.get(&'\n').unwrap().key.visual.name, "enter");
+
+    // The same mapping on a different board
+    let mut ortho = Layout::default();
+    let ortho = init_mapped(&mut ortho, "boards/ortho.board", "mappings/dvorak.map").unwrap();
+    assert_eq!(ortho.char_keys.get(&'e').unwrap().key.pos.x, 3.0);
+    assert_eq!(ortho.char_keys.get(&'e').unwrap().key.pos.y, 2.0);
+  }
+
+  #[test]
+  fn test_levels() {
+    let mut lay = Layout::default();
+    let lay = init_mapped(&mut lay, "test/levels.board", "test/levels.map").unwrap();
+
+    // Board chars stay on keys the mapping leaves alone
+    assert_eq!(lay.char_keys.get(&'x').unwrap().key.visual.name, "key0");
+
+    let a = lay.char_keys.get(&'á').unwrap();
+    assert_eq!(a.key.visual.name, "key1");
+    assert_eq!(a.mods.as_ref().unwrap()[0].visual.name, "ralt");
+    let mods: Vec<&str> = lay.char_keys.get(&'Á').unwrap().mods.as_ref().unwrap().iter().map(|m| m.visual.name.as_str()).collect();
+    assert_eq!(mods, vec!["ralt", "lshift"]);
+
+    // Skipped levels
+    assert_eq!(lay.char_keys.get(&'€').unwrap().key.visual.name, "key2");
+    assert_eq!(lay.char_keys.get(&'€').unwrap().mods.as_ref().unwrap().len(), 2);
+    assert!(!lay.char_keys.contains_key(&'\0'));
+
+    assert_eq!(lay.combos_for('ê').unwrap().len(), 2);
+
+    // Keys that aren't on the board are left out
+    assert!(!lay.char_keys.contains_key(&'o'));
+  }
+
+  #[test]
+  fn test_chords() {
+    let mut lay = Layout::default();
+    let lay = init(&mut lay, "layouts/chorded.layout").unwrap();
+
+    let r = lay.chord_for("r").unwrap();
+    assert_eq!(r.key.visual.name, "e");
+    assert_eq!(r.mods.unwrap()[0].visual.name, "t");
+
+    // Capitals add shift
+    let the = lay.chord_for("The").unwrap();
+    let mods: Vec<&str> = the.mods.unwrap().iter().map(|m| m.visual.name.as_str()).collect();
+    assert_eq!(mods, vec!["n", "e", "shift"]);
+    assert!(lay.chord_for("THE").is_none());
+
+    let mut lay = Layout::default();
+    let lay = init(&mut lay, "test/chords.layout").unwrap();
+    assert_eq!(lay.chord_for("dab").unwrap().mods.unwrap().len(), 3);
+
+    // A finger can't press two keys of a chord
+    let mut lay = Layout::default();
+    assert!(init(&mut lay, "test/chord_finger.layout").is_none());
+  }
+
   #[test]
   fn test_homes() {
     let mut lay = Layout::default();
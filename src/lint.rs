@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+
+use super::layout;
+
+// Chars every layout is checked against unless given a text
+static DEFAULT_ALPHABET: &str =
+  " !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~\n";
+
+// Keys sharing less than this much (in u) are only touching
+static OVERLAP_EPSILON: f32 = 0.01;
+
+pub struct Warning {
+  pub line: usize, // 0 for the layout as a whole
+  pub msg: String,
+}
+
+fn warn(warnings: &mut Vec<Warning>, line: usize, msg: String) {
+  warnings.push(Warning { line, msg });
+}
+
+// Check a layout for mistakes that init accepts but give nonsense
+// results. Warnings are sorted by line
+pub fn lint(lay: &layout::Layout, alphabet: Option<&str>) -> Vec<Warning> {
+  let mut warnings = Vec::new();
+
+  let mut keys: Vec<&layout::Key> = lay.keys.iter().chain(lay.mod_map.values()).collect();
+  keys.sort_by_key(|k| k.line);
+
+  check_chars(lay, &mut warnings);
+  check_fingers(lay, &keys, &mut warnings);
+  check_hands(lay, &mut warnings);
+  check_overlaps(&keys, &mut warnings);
+
+  let mut missing = Vec::new();
+  for c in alphabet.unwrap_or(DEFAULT_ALPHABET).chars() {
+    let chord = lay.chord_for(c.encode_utf8(&mut [0; 4]));
+    if lay.combos_for(c).is_none() && chord.is_none() && !missing.contains(&c) {
+      missing.push(c);
+    }
+  }
+  if !missing.is_empty() {
+    let list: Vec<String> = missing.iter().map(|c| format!("{:?}", c)).collect();
+    warn(&mut warnings, 0, format!("Can't type {}", list.join(" ")));
+  }
+
+  warnings.sort_by_key(|w| w.line);
+  warnings
+}
+
+// Chars on more than one key, and chars that need a missing modifier
+fn check_chars(lay: &layout::Layout, warnings: &mut Vec<Warning>) {
+  let mut seen: HashMap<char, &layout::Key> = HashMap::new();
+
+  for key in &lay.keys {
+    let mut chars = vec![key.pressed, key.shifted];
+    chars.extend(key.levels.iter());
+    for c in chars.into_iter().filter(|c| *c != '\0') {
+      match seen.get(&c) {
+        Some(other) if std::ptr::eq(*other, key) => {
+          warn(warnings, key.line, format!("{:?} is on key {} twice", c, key.visual.name))
+        }
+        Some(other) => warn(
+          warnings,
+          key.line,
+          format!(
+            "{:?} is on key {} and key {} (line {}), only one of them is used",
+            c, key.visual.name, other.visual.name, other.line
+          ),
+        ),
+        None => {
+          seen.insert(c, key);
+        }
+      }
+    }
+
+    if key.shifted != '\0' && lay.modifier_for("shift", key.finger).is_none() {
+      warn(
+        warnings,
+        key.line,
+        format!("Key {} has shifted char {:?} but there's no shift key", key.visual.name, key.shifted),
+      );
+    }
+    for c in key.levels.iter().filter(|c| **c != '\0') {
+      if !lay.char_keys.contains_key(c) {
+        warn(
+          warnings,
+          key.line,
+          format!("Key {} has {:?} on an AltGr level but there's no altgr or ralt key", key.visual.name, c),
+        );
+      }
+    }
+  }
+}
+
+// Keys without a usable finger, and fingers without a single home
+fn check_fingers(lay: &layout::Layout, keys: &[&layout::Key], warnings: &mut Vec<Warning>) {
+  let mut first_home: HashMap<i16, &layout::Key> = HashMap::new();
+  let mut reported = Vec::new();
+  // Fingers past the last one with a home are likely typos
+  let homed = lay.homes.iter().rposition(|h| !std::ptr::eq(*h, &layout::DUMMY_KEY)).map_or(0, |i| i + 1) as i16;
+
+  for key in keys {
+    let f = key.finger;
+    if f < 0 {
+      warn(warnings, key.line, format!("Key {} has no finger number", key.visual.name));
+      continue;
+    }
+
+    let has_home = !std::ptr::eq(lay.homes[f as usize], &layout::DUMMY_KEY);
+    if !has_home && !reported.contains(&f) {
+      reported.push(f);
+      let msg = if f >= homed {
+        format!("Key {} uses finger {}, which is out of range and has no home", key.visual.name, f)
+      } else {
+        format!("Finger {} has no home key, but key {} uses it", f, key.visual.name)
+      };
+      warn(warnings, key.line, msg);
+    }
+
+    if key.is_home {
+      match first_home.get(&f) {
+        Some(other) => warn(
+          warnings,
+          key.line,
+          format!(
+            "Key {} is a second home for finger {} after key {} (line {})",
+            key.visual.name, f, other.visual.name, other.line
+          ),
+        ),
+        None => {
+          first_home.insert(f, key);
+        }
+      }
+    }
+  }
+}
+
+// Home keys on the wrong side of the line halfway between the average
+// left and right hand homes. Thumbs sit in the middle, so they're
+// left out
+fn check_hands(lay: &layout::Layout, warnings: &mut Vec<Warning>) {
+  let homed: Vec<(usize, &layout::Finger, &layout::Key)> = (0..lay.fingers.len())
+    .map(|i| (i, &lay.fingers[i], lay.homes[i]))
+    .filter(|(_, f, k)| !f.is_thumb() && !std::ptr::eq(*k, &layout::DUMMY_KEY))
+    .collect();
+
+  let mean_x = |hand| {
+    let xs: Vec<f32> = homed.iter().filter(|(_, f, _)| f.hand == hand).map(|(_, _, k)| k.center().x).collect();
+    if xs.is_empty() {
+      None
+    } else {
+      Some(xs.iter().sum::<f32>() / xs.len() as f32)
+    }
+  };
+  let split = match (mean_x(layout::Hand::Left), mean_x(layout::Hand::Right)) {
+    (Some(l), Some(r)) => (l + r) / 2.0,
+    _ => return,
+  };
+
+  for (i, finger, key) in homed.iter() {
+    let x = key.center().x;
+    let wrong = match finger.hand {
+      layout::Hand::Left => x > split,
+      layout::Hand::Right => x < split,
+    };
+    if wrong {
+      warn(
+        warnings,
+        key.line,
+        format!("Home key {} of finger {} ({:?} hand) is on the other hand's side", key.visual.name, i, finger.hand),
+      );
+    }
+  }
+}
+
+fn check_overlaps(keys: &[&layout::Key], warnings: &mut Vec<Warning>) {
+  for (i, key) in keys.iter().enumerate() {
+    for other in &keys[..i] {
+      if overlap(key, other) {
+        warn(
+          warnings,
+          key.line,
+          format!("Key {} overlaps key {} (line {})", key.visual.name, other.visual.name, other.line),
+        );
+      }
+    }
+  }
+}
+
+// Separating axis test on the (possibly rotated) rectangles
+fn overlap(a: &layout::Key, b: &layout::Key) -> bool {
+  let (ca, cb) = (a.corners(), b.corners());
+
+  for corners in [ca, cb].iter() {
+    for i in 0..2 {
+      let axis = layout::Pos {
+        x: corners[i + 1].x - corners[i].x,
+        y: corners[i + 1].y - corners[i].y,
+      };
+      let len = (axis.x * axis.x + axis.y * axis.y).sqrt();
+      if len == 0.0 {
+        return false;
+      }
+
+      let project = |ps: &[layout::Pos; 4]| {
+        let dots = ps.iter().map(|p| (p.x * axis.x + p.y * axis.y) / len);
+        dots.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), d| (lo.min(d), hi.max(d)))
+      };
+      let (a_lo, a_hi) = project(&ca);
+      let (b_lo, b_hi) = project(&cb);
+      if a_hi - b_lo < OVERLAP_EPSILON || b_hi - a_lo < OVERLAP_EPSILON {
+        return false;
+      }
+    }
+  }
+
+  true
+}
+
+pub fn print_report(path: &str, warnings: &[Warning]) {
+  for w in warnings {
+    if w.line == 0 {
+      println!("{}: {}", path, w.msg);
+    } else {
+      println!("{}:{}: {}", path, w.line, w.msg);
+    }
+  }
+  println!("{} warning(s)", warnings.len());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mistakes() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "test/lint.layout").unwrap();
+
+    let warnings = lint(lay, Some("adq"));
+    let lines: Vec<usize> = warnings.iter().map(|w| w.line).collect();
+    assert_eq!(lines, vec![0, 3, 4, 8, 8, 9, 10]);
+
+    assert_eq!(warnings[0].msg, "Can't type 'q'");
+    assert!(warnings[1].msg.contains("'a' is on key s and key a (line 2)"));
+    assert!(warnings[2].msg.starts_with("Finger 2 has no home key"));
+    assert!(warnings[3..5].iter().any(|w| w.msg.starts_with("Key k is a second home for finger 5")));
+    assert!(warnings[3..5].iter().any(|w| w.msg.starts_with("Home key k of finger 5")));
+    assert!(warnings[5].msg.contains("out of range"));
+    assert!(warnings[6].msg.starts_with("Key o overlaps key k"));
+  }
+
+  #[test]
+  fn shipped_layouts() {
+    let full = [
+      "layouts/qwerty.layout",
+      "layouts/dvorak.layout",
+      "layouts/colemak.layout",
+      "layouts/qwerty-intl.layout",
+    ];
+    for path in full.iter() {
+      let mut lay = layout::Layout::default();
+      let lay = layout::init(&mut lay, path).unwrap();
+      assert!(lint(lay, None).is_empty(), "{} has lint warnings", path);
+    }
+
+    // These only type part of the default alphabet
+    let partial = [
+      "layouts/split-qwerty.layout",
+      "layouts/chorded.layout",
+      "layouts/mobile-qwerty.layout",
+    ];
+    for path in partial.iter() {
+      let mut lay = layout::Layout::default();
+      let lay = layout::init(&mut lay, path).unwrap();
+      let warnings: Vec<String> =
+        lint(lay, None).into_iter().map(|w| w.msg).filter(|m| !m.starts_with("Can't type")).collect();
+      assert!(warnings.is_empty(), "{} has lint warnings: {:?}", path, warnings);
+    }
+  }
+}
@@ -4,17 +4,53 @@ use std::env;
 use std::time::Duration;
 
 mod analyze;
+mod bigram;
+mod breakdown;
+mod calibrate;
+mod coverage;
+mod diff;
+mod dictionary;
 mod display;
+mod effort;
+mod event;
+mod input;
+mod keylog;
 mod layout;
+mod lint;
+mod metric;
+mod model;
 mod playback;
+mod workload;
+
+enum Command {
+  Run,
+  Lint,
+  Diff(String), // The layout to compare to
+  Calibrate(String), // The keystroke log to fit the timing to
+}
 
 struct ProgOptions {
+  command: Command,
   lay_path: String,
+  map_path: Option<String>,
+  other_map_path: Option<String>,
   file_path: Option<String>,
   text: Option<String>,
+  workload_path: Option<String>,
+  log_path: Option<String>,
+  out_path: Option<String>,
+  events_path: Option<String>,
+  bigram_path: Option<String>,
+  dist_path: Option<String>,
+  model: model::Model,
+  ranking: analyze::Ranking,
   animate: bool,
   parallel: bool,
   compare: bool,
+  distribution: bool,
+  breakdown: bool,
+  color_words: bool,
+  strict: bool,
 }
 
 pub fn main() {
@@ -25,43 +61,120 @@ pub fn main() {
     None => return
   };
 
-  if options.compare {
+  if let Command::Lint = options.command {
+    run_lint(&options);
+  } else if let Command::Diff(other_path) = &options.command {
+    run_diff(&options, other_path);
+  } else if let Command::Calibrate(log_path) = &options.command {
+    run_calibrate(&options, log_path);
+  } else if let Some(path) = &options.workload_path {
+    let mut lay = layout::Layout::default();
+
+    let lay = match load_layout(&mut lay, &options.lay_path, &options.map_path) {
+      Some(l) => l,
+      None => return,
+    };
+    let workload = match workload::read_workload(path) {
+      Some(w) => w,
+      None => return,
+    };
+    let costs = workload::analyze_workload(workload, lay, &options.model);
+    workload::print_report(&costs);
+  } else if options.compare {
     assert!(options.file_path.is_some(), "Comparing requires a text file");
     let mut lay = layout::Layout::default();
 
-    let lay = match layout::init(&mut lay, options.lay_path.as_str()) {
+    let lay = match load_layout(&mut lay, &options.lay_path, &options.map_path) {
       Some(l) => l,
       None => return,
     };
-    let longest = analyze::compare_lines(options.file_path.as_ref().unwrap(), lay);
+    let text = analyze::read_file(options.file_path.as_ref().unwrap());
+    if !check_coverage(&text, lay, options.strict) {
+      std::process::exit(1);
+    }
+    // The distribution needs every line, not just the top ones
+    let distribution = options.distribution || options.dist_path.is_some();
+    let all = analyze::Ranking {
+      count: if distribution { usize::MAX } else { options.ranking.count },
+      ..options.ranking.clone()
+    };
+    let words = analyze::compare_lines(&text, lay, &options.model, &all);
+    let ranked = &words[..words.len().min(options.ranking.count)];
 
-    let mut count = 1;
-    for word in longest {
-      println!("{:3}: {} is {}mm long and {} letters long", count, word.1, word.0.total_dist_mm(), word.0.total_chars);
-      count += 1;
+    println!("Ranked by {:?}:", options.ranking.by);
+    for (i, r) in ranked.iter().enumerate() {
+      println!(
+        "{:3}: {} is {}mm long and {} letters long ({})",
+        i + 1,
+        r.line,
+        r.tl.total_dist_mm(),
+        r.tl.total_chars,
+        r.value
+      );
+    }
+    if let Some(path) = &options.out_path {
+      if let Err(e) = analyze::write_ranking_csv(ranked, path) {
+        println!("Couldn't write {}: {}", path, e);
+      }
+    }
+    if options.distribution {
+      dictionary::print_report(&words);
+    }
+    if let Some(path) = &options.dist_path {
+      if let Err(e) = dictionary::write_csv(&words, path) {
+        println!("Couldn't write {}: {}", path, e);
+      }
     }
   } else if options.animate {
-    play_anim(&options.lay_path, &options.text);
-  } else {
-    get_stats(&options.lay_path, &options.text, &options.file_path, options.parallel);
+    if !play_anim(&options) {
+      std::process::exit(1);
+    }
+  } else if !get_stats(&options, options.parallel, options.strict) {
+    std::process::exit(1);
   }
 }
 
 fn parse_args(args: &[String]) -> Option<ProgOptions> {
+  let mut command = Command::Run;
   let mut lay_path = "layouts/qwerty.layout".to_string();
+  let mut map_path = None;
+  let mut other_map_path = None;
   let mut file_path = None;
   let mut text = None;
+  let mut workload_path = None;
+  let mut log_path = None;
+  let mut out_path = None;
+  let mut events_path = None;
+  let mut bigram_path = None;
+  let mut dist_path = None;
+  let mut error_rate = None;
+  let mut seed = 0;
+  let mut timing = model::Timing::default();
+  let mut shift = model::ShiftModel::default();
+  let mut mobile = None;
+  let mut effort = None;
+  let mut ranking = analyze::Ranking::default();
   let mut animate = true;
   let mut parallel = false;
   let mut compare = false;
+  let mut distribution = false;
+  let mut breakdown = false;
+  let mut color_words = false;
+  let mut strict = false;
 
   let mut i = 1;
   while i < args.len() {
     // TODO: This doesn't feel like the right way to do this
     match args[i].as_str() {
       "-h" | "--help" => print_help(),
+      "lint" => command = Command::Lint,
       "-n" => animate = false,
       "-c" => compare = true,
+      "--asc" => ranking.ascending = true,
+      "-d" => distribution = true,
+      "-a" => breakdown = true,
+      "-C" => color_words = true,
+      "-s" => strict = true,
       f => {
         if i + 1 >= args.len() {
           println!("Flag {} needs a value or unrecognized", f);
@@ -71,8 +184,67 @@ fn parse_args(args: &[String]) -> Option<ProgOptions> {
 
         match f {
           "-l" => lay_path = val.clone(),
+          "-m" => map_path = Some(val.clone()),
+          "-M" => other_map_path = Some(val.clone()),
+          "diff" => command = Command::Diff(val.clone()),
+          "calibrate" => command = Command::Calibrate(val.clone()),
           "-t" => text = Some(val.clone()),
           "-f" => file_path = Some(val.clone()),
+          "-w" => workload_path = Some(val.clone()),
+          "-k" => log_path = Some(val.clone()),
+          "-o" => out_path = Some(val.clone()),
+          "-v" => events_path = Some(val.clone()),
+          "-b" => bigram_path = Some(val.clone()),
+          "-D" => dist_path = Some(val.clone()),
+          "-e" => match val.parse::<f32>() {
+            Ok(rate) if (0.0..=1.0).contains(&rate) => error_rate = Some(rate),
+            Ok(_) => {println!("Error rate {} isn't between 0 and 1", val); return None;}
+            Err(_) => {println!("Error rate {} isn't a number", val); return None;}
+          },
+          "-r" => match val.parse::<u64>() {
+            Ok(s) => seed = s,
+            Err(_) => {println!("Seed {} isn't a number", val); return None;}
+          },
+          "-S" => match model::parse_shift_policy(val) {
+            Some(p) => shift.policy = p,
+            None => {println!("Unknown shift policy {}", val); return None;}
+          },
+          "-L" => match val.parse::<usize>() {
+            Ok(n) => shift.caps_lock_min = Some(n),
+            Err(_) => {println!("Caps lock run {} isn't a number", val); return None;}
+          },
+          "-W" => match val.parse::<usize>() {
+            Ok(n) => shift.caps_word_min = Some(n),
+            Err(_) => {println!("Caps Word run {} isn't a number", val); return None;}
+          },
+          "-T" => match model::read_timing(val) {
+            Some(t) => timing = t,
+            None => return None,
+          },
+          "-E" => match effort::read_config(val) {
+            Some(c) => effort = Some(c),
+            None => return None,
+          },
+          "-P" => match val.parse::<f32>() {
+            Ok(key_pitch) => mobile = Some(model::MobileModel { key_pitch }),
+            Err(_) => {println!("Key pitch {} isn't a number", val); return None;}
+          },
+          "-N" => match val.parse::<usize>() {
+            Ok(n) => ranking.count = n,
+            Err(_) => {println!("Count {} isn't a number", val); return None;}
+          },
+          "-R" => match analyze::parse_rank_by(val) {
+            Some(by) => ranking.by = by,
+            None => {println!("Unknown ranking {}", val); return None;}
+          },
+          "--min-len" => match val.parse::<usize>() {
+            Ok(n) => ranking.min_len = n,
+            Err(_) => {println!("Minimum length {} isn't a number", val); return None;}
+          },
+          "--max-len" => match val.parse::<usize>() {
+            Ok(n) => ranking.max_len = Some(n),
+            Err(_) => {println!("Maximum length {} isn't a number", val); return None;}
+          },
           "-p" => parallel = val == "true",
           unknown => {println!("Flag {} unrecognized", unknown); return None;}
         }
@@ -84,54 +256,257 @@ fn parse_args(args: &[String]) -> Option<ProgOptions> {
     i += 1;
   }
 
+  let model = model::Model {
+    timing,
+    errors: error_rate.map(|rate| model::ErrorModel::new(rate, seed)),
+    shift,
+    mobile,
+    effort,
+    metrics: None,
+  };
+
   return Some(ProgOptions{
+    command,
     lay_path,
+    map_path,
+    other_map_path,
     file_path,
     text,
+    workload_path,
+    log_path,
+    out_path,
+    events_path,
+    bigram_path,
+    dist_path,
+    model,
+    ranking,
     animate,
     parallel,
     compare,
+    distribution,
+    breakdown,
+    color_words,
+    strict,
   })
 }
 
 fn print_help() {
-  println!("Usage: layout-speed [OPTIONS] [TEXT]");
+  println!("Usage: layout-speed [COMMAND] [OPTIONS]");
+  println!("Commands:");
+  println!("  lint\t\t\tCheck the layout for mistakes, using the chars in -t or -f as the alphabet it should type");
+  println!("  diff FILE\t\tCompare the -l layout to the one in FILE, with stats on the -t or -f text");
+  println!("  calibrate LOG\t\tFit the timing to the keystroke log LOG and write it to the -o file");
   println!("Options:");
   println!("  -h, --help\t\tPrint this message");
   println!("  -l FILE\t\tUse PATH as the keyboard layout instead of the default qwerty.layout");
+  println!("  -m FILE\t\tPut the chars in the mapping FILE on the board given with -l");
+  println!("  -M FILE\t\tWith diff, put the chars in the mapping FILE on the board given to diff");
   println!("  -t STRING\t\tAnalyze the given STRING");
   println!("  -f FILE\t\tAnalyze the contents of FILE");
+  println!("  -w FILE\t\tReport the cost of each shortcut in the workload FILE");
+  println!("  -k FILE\t\tReplay the keystroke log FILE instead of simulating a text");
+  println!("  -o FILE\t\tAlso write the per finger stats to FILE as CSV");
+  println!("  -b FILE\t\tAlso write the time and distance of every bigram in the text to FILE, as CSV, JSON or an SVG heatmap");
+  println!("  -v FILE\t\tAlso write every finger move and key press to FILE as CSV");
+  println!("  -e RATE\t\tSimulate typing errors, RATE (0 to 1) is the chance of one on a home key");
+  println!("  -r SEED\t\tSeed for the error simulation");
+  println!("  -S POLICY\t\tWhich shift to use: opposite (default), same, left, right or sticky");
+  println!("  -L N\t\t\tUse caps lock for runs of N or more capitals");
+  println!("  -W N\t\t\tUse Caps Word for runs of N or more capitals");
+  println!("  -T FILE\t\tUse the timing (press length, speeds) in FILE, as written by calibrate");
+  println!("  -E FILE\t\tAlso score the text with the Carpalx style effort model weighted by FILE");
+  println!("  -P MM\t\t\tMobile mode: keys are MM apart, thumbs don't return home, and swipe paths are reported");
   println!("  -p true/false\t\tWhether to analyze the text or file in parallel");
   println!("  -n\t\t\tOnly generate statistics on the text, without the animation");
-  println!("  -c\t\t\tCompare each line of the given file and output the longest one");
+  println!("  -a\t\t\tWith -n, also break the time, distance and same finger bigrams down by word and sentence");
+  println!("  -C\t\t\tColour the words of the animated text by how long they take");
+  println!("  -c\t\t\tCompare each line of the given file and output the longest ones, and write them to the -o file as CSV");
+  println!("  -N COUNT\t\tWith -c, how many lines to output (default 100)");
+  println!("  -R KEY\t\tWith -c, rank by distance (default), time, per-char, sfb, alternation or effort");
+  println!("  --asc\t\t\tWith -c, output the lowest ranked lines first");
+  println!("  --min-len N\t\tWith -c, skip lines shorter than N chars");
+  println!("  --max-len N\t\tWith -c, skip lines longer than N chars");
+  println!("  -d\t\t\tWith -c, also print histograms and percentiles over every line, and the lines longest and shortest for their letter count");
+  println!("  -D FILE\t\tWith -c, write every line's stats and how far it is from what its letter count predicts to FILE as CSV");
+  println!("  -s\t\t\tStrict mode, exit with an error if the layout can't type every character");
   std::process::exit(0);
 }
 
-fn get_stats(lay_path: &str, text: &Option<String>, file_path: &Option<String>, parallel: bool) {
-  let text = match text {
-    Some(t) => t,
-    None => "The quick brown fox jumps over the lazy dog.",
+// Returns false if strict mode stopped it
+fn get_stats(options: &ProgOptions, parallel: bool, strict: bool) -> bool {
+  let text = match (&options.file_path, &options.text) {
+    (Some(p), _) => analyze::read_file(p),
+    (None, Some(t)) => t.clone(),
+    (None, None) => "The quick brown fox jumps over the lazy dog.".to_string(),
   };
   let mut lay = layout::Layout::default();
 
-  let lay = match layout::init(&mut lay, lay_path) {
+  let lay = match load_layout(&mut lay, &options.lay_path, &options.map_path) {
     Some(l) => l,
-    None => return,
+    None => return true,
   };
 
-  let tl = match file_path {
-    Some(p) => analyze::gen_timeline_file(p, parallel, lay),
-    None => analyze::gen_timeline(text, false, lay),
+  let tl = match &options.log_path {
+    Some(path) => match keylog::read_log(path, lay) {
+      Some(presses) => analyze::gen_timeline_log(&presses, false, lay, &options.model),
+      None => return true,
+    },
+    None => {
+      if !check_coverage(&text, lay, strict) {
+        return false;
+      }
+      analyze::gen_timeline_text(&text, parallel, lay, &options.model)
+    }
   };
 
   analyze::print_timeline(&tl);
+
+  if let Some(path) = &options.out_path {
+    if let Err(e) = analyze::write_finger_csv(&tl, path) {
+      println!("Couldn't write {}: {}", path, e);
+    }
+  }
+  if let (Some(path), None) = (&options.bigram_path, &options.log_path) {
+    let bigrams = bigram::bigrams(&text, lay, &options.model);
+    if let Err(e) = bigram::write(&bigrams, path, &options.lay_path) {
+      println!("Couldn't write {}: {}", path, e);
+    }
+  }
+  if let (true, None) = (options.breakdown, &options.log_path) {
+    breakdown::print_breakdown(&text, &breakdown::breakdown(&text, lay, &options.model));
+  }
+  // Recorded sessions don't have simulated events
+  if let (Some(path), None) = (&options.events_path, &options.log_path) {
+    if let Err(e) = event::write_csv(analyze::events(&text, lay, &options.model), path) {
+      println!("Couldn't write {}: {}", path, e);
+    }
+  }
+  true
 }
 
-fn play_anim(lay_path: &str, text: &Option<String>) {
-  let text = match text {
-    Some(t) => t,
-    None => "The quick brown fox jumps over the lazy dog.",
+fn run_lint(options: &ProgOptions) {
+  let alphabet = match (&options.file_path, &options.text) {
+    (Some(p), _) => Some(analyze::read_file(p)),
+    (None, t) => t.clone(),
+  };
+  let mut lay = layout::Layout::default();
+
+  let lay = match load_layout(&mut lay, &options.lay_path, &options.map_path) {
+    Some(l) => l,
+    None => std::process::exit(1),
+  };
+
+  let warnings = lint::lint(lay, alphabet.as_deref());
+  lint::print_report(&options.lay_path, &warnings);
+  if !warnings.is_empty() {
+    std::process::exit(1);
+  }
+}
+
+fn run_diff(options: &ProgOptions, other_path: &str) {
+  let text = match (&options.file_path, &options.text) {
+    (Some(p), _) => analyze::read_file(p),
+    (None, Some(t)) => t.clone(),
+    (None, None) => "The quick brown fox jumps over the lazy dog.".to_string(),
+  };
+
+  let mut old = layout::Layout::default();
+  let old = match load_layout(&mut old, &options.lay_path, &options.map_path) {
+    Some(l) => l,
+    None => return,
+  };
+  let mut new = layout::Layout::default();
+  let new = match load_layout(&mut new, other_path, &options.other_map_path) {
+    Some(l) => l,
+    None => return,
   };
+
+  diff::print_diff(old, new);
+  let old_tl = analyze::gen_timeline_text(&text, options.parallel, old, &options.model);
+  let new_tl = analyze::gen_timeline_text(&text, options.parallel, new, &options.model);
+  diff::print_metrics(&old_tl, &new_tl);
+}
+
+fn run_calibrate(options: &ProgOptions, log_path: &str) {
+  let mut lay = layout::Layout::default();
+  let lay = match load_layout(&mut lay, &options.lay_path, &options.map_path) {
+    Some(l) => l,
+    None => return,
+  };
+  let presses = match keylog::read_log(log_path, lay) {
+    Some(p) => p,
+    None => return,
+  };
+
+  let fit = match calibrate::calibrate(&presses, lay, &options.model) {
+    Some(f) => f,
+    None => return,
+  };
+  calibrate::print_fit(&fit);
+
+  if let Some(path) = &options.out_path {
+    if let Err(e) = model::write_timing(&fit.timing, path) {
+      println!("Couldn't write {}: {}", path, e);
+    }
+  }
+}
+
+// The -l file is a whole layout, or a board if there's a mapping
+fn load_layout<'a>(lay: &'a mut layout::Layout<'a>, lay_path: &str, map_path: &Option<String>) -> Option<&'a layout::Layout<'a>> {
+  match map_path {
+    Some(m) => layout::init_mapped(lay, lay_path, m),
+    None => layout::init(lay, lay_path),
+  }
+}
+
+// Report chars the layout can't type. Returns false if the caller
+// should stop because of strict mode
+fn check_coverage(text: &str, lay: &layout::Layout, strict: bool) -> bool {
+  let cov = coverage::check(text, lay);
+  if cov.is_complete() {
+    return true;
+  }
+
+  coverage::print_report(&cov);
+  if strict {
+    println!("Strict mode: layout can't type every character in the text");
+    return false;
+  }
+
+  true
+}
+
+// Returns false if strict mode stopped it
+fn play_anim(options: &ProgOptions) -> bool {
+  let model = &options.model;
+  let mut lay = layout::Layout::default();
+
+  let lay = match load_layout(&mut lay, &options.lay_path, &options.map_path) {
+    Some(l) => l,
+    None => return true,
+  };
+
+  let (text, tl) = match &options.log_path {
+    Some(path) => {
+      let presses = match keylog::read_log(path, lay) {
+        Some(p) => p,
+        None => return true,
+      };
+      (keylog::typed_text(&presses), analyze::gen_timeline_log(&presses, true, lay, model))
+    }
+    None => {
+      let text = match &options.text {
+        Some(t) => t.clone(),
+        None => "The quick brown fox jumps over the lazy dog.".to_string(),
+      };
+      if !check_coverage(&text, lay, options.strict) {
+        return false;
+      }
+      let tl = analyze::gen_timeline_model(&text, true, lay, model);
+      (text, tl)
+    }
+  };
+
   let (context, canvas, ttf) = display::init("Layout Speed").unwrap();
   let font = display::init_font(&ttf);
   let mut disp = display::Data {
@@ -141,15 +516,12 @@ fn play_anim(lay_path: &str, text: &Option<String>) {
     font,
   };
 
-  let mut lay = layout::Layout::default();
-
-  let lay = match layout::init(&mut lay, lay_path) {
-    Some(l) => l,
-    None => return,
-  };
-
-  let tl = analyze::gen_timeline(text, true, lay);
   analyze::print_timeline(&tl);
+  // Only simulated text has a cost per word
+  let words = match (options.color_words, &options.log_path) {
+    (true, None) => breakdown::breakdown(&text, lay, model).words,
+    _ => Vec::new(),
+  };
 
   let mut playhead = playback::Playhead {
     time: 0,
@@ -181,9 +553,15 @@ fn play_anim(lay_path: &str, text: &Option<String>) {
     display::draw_layout(lay, &mut disp);
     display::draw_playdata(&playdata, &mut disp);
 
-    display::draw_text(10, 255, format!("\"{}\"", text).as_str(), &mut disp);
+    if words.is_empty() {
+      display::draw_text(10, 255, format!("\"{}\"", text).as_str(), &mut disp);
+    } else {
+      display::draw_words(10, 255, &text, &words, &mut disp);
+    }
     display::draw_text(10, 275, analyze::stats_string(&tl).as_str(), &mut disp);
     disp.canvas.present();
     ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
   }
+
+  true
 }
@@ -0,0 +1,207 @@
+use super::layout;
+
+// One key pressed during a simulation
+pub struct Keystroke<'a> {
+  pub ch: Option<char>, // Char of the text being typed, None for tags, chords and typos
+  pub key: &'a layout::Key,
+  pub finger: usize,
+  pub hand: Option<layout::Hand>, // None if the layout doesn't say
+  pub thumb: bool,
+  pub start: i32,     // ms the press starts
+  pub end: i32,       // ms the press ends
+  pub modifier: bool, // Held for the combo's main key
+}
+
+// A statistic measured during a simulation. Metrics see every combo
+// and finger move, and give named totals at the end
+pub trait Metric {
+  // Each combo pressed, with a keystroke per key, modifiers first
+  fn keystrokes(&mut self, _keystrokes: &[Keystroke]) {}
+
+  // Each finger move, including going back home
+  fn travel(&mut self, _finger: usize, _from: &layout::Pos, _to: &layout::Pos) {}
+
+  // Parallel runs add up the values of each slice, so these should be
+  // totals rather than averages
+  fn values(&self) -> Vec<(String, f32)>;
+}
+
+// Makes the metrics for one simulation, see model::Model
+pub type MetricSet = fn() -> Vec<Box<dyn Metric>>;
+
+pub struct Distance {
+  pub total: f32,          // in u
+  pub per_finger: Vec<f32>, // in u
+}
+
+impl Distance {
+  pub fn new(fingers: usize) -> Self {
+    Distance {
+      total: 0.0,
+      per_finger: vec![0.0; fingers],
+    }
+  }
+}
+
+impl Metric for Distance {
+  fn travel(&mut self, finger: usize, from: &layout::Pos, to: &layout::Pos) {
+    let dist = super::analyze::move_dist(from, to);
+    self.total += dist;
+    self.per_finger[finger] += dist;
+  }
+
+  fn values(&self) -> Vec<(String, f32)> {
+    vec![("Distance (u)".to_string(), self.total)]
+  }
+}
+
+// Presses by each finger, including modifiers, and how long they
+// were held
+pub struct Usage {
+  pub per_finger: Vec<u32>,
+  pub press_time: Vec<i32>, // ms
+  pub modifiers: u32,
+}
+
+impl Usage {
+  pub fn new(fingers: usize) -> Self {
+    Usage {
+      per_finger: vec![0; fingers],
+      press_time: vec![0; fingers],
+      modifiers: 0,
+    }
+  }
+}
+
+impl Metric for Usage {
+  fn keystrokes(&mut self, keystrokes: &[Keystroke]) {
+    for ks in keystrokes {
+      self.per_finger[ks.finger] += 1;
+      self.press_time[ks.finger] += ks.end - ks.start;
+      if ks.modifier {
+        self.modifiers += 1;
+      }
+    }
+  }
+
+  fn values(&self) -> Vec<(String, f32)> {
+    vec![
+      ("Presses".to_string(), self.per_finger.iter().sum::<u32>() as f32),
+      ("Modifier presses".to_string(), self.modifiers as f32),
+    ]
+  }
+}
+
+// Combos that don't use a hand the combo before used. Thumbs don't
+// count as either hand
+#[derive(Default)]
+pub struct Switches {
+  pub total: u32,
+  prev_left: bool,
+  prev_right: bool,
+}
+
+impl Metric for Switches {
+  fn keystrokes(&mut self, keystrokes: &[Keystroke]) {
+    let uses = |hand| keystrokes.iter().any(|ks| !ks.thumb && ks.hand == Some(hand));
+    let left = uses(layout::Hand::Left);
+    let right = uses(layout::Hand::Right);
+
+    if !((left && self.prev_left) || (right && self.prev_right)) {
+      self.total += 1;
+    }
+    self.prev_left = left;
+    self.prev_right = right;
+  }
+
+  fn values(&self) -> Vec<(String, f32)> {
+    vec![("Switches".to_string(), self.total as f32)]
+  }
+}
+
+pub static SAME_FINGER: &str = "Same finger bigrams";
+
+// Chars of the text whose main key is pressed by the same finger as
+// the last char's, but isn't the same key. Typos don't count
+#[derive(Default)]
+pub struct SameFinger {
+  pub total: u32,
+  prev: Option<(usize, layout::Pos)>,
+}
+
+impl Metric for SameFinger {
+  fn keystrokes(&mut self, keystrokes: &[Keystroke]) {
+    let main = match keystrokes.last() {
+      Some(ks) if ks.ch.is_some() => ks,
+      _ => return,
+    };
+    if let Some((finger, pos)) = self.prev {
+      if finger == main.finger && (pos.x != main.key.pos.x || pos.y != main.key.pos.y) {
+        self.total += 1;
+      }
+    }
+    self.prev = Some((main.finger, main.key.pos));
+  }
+
+  fn values(&self) -> Vec<(String, f32)> {
+    vec![(SAME_FINGER.to_string(), self.total as f32)]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::analyze;
+  use super::super::model;
+  use super::*;
+
+  // Counts presses of the left index finger
+  #[derive(Default)]
+  struct LeftIndex {
+    presses: u32,
+  }
+
+  impl Metric for LeftIndex {
+    fn keystrokes(&mut self, keystrokes: &[Keystroke]) {
+      self.presses += keystrokes.iter().filter(|ks| ks.finger == 3).count() as u32;
+    }
+
+    fn values(&self) -> Vec<(String, f32)> {
+      vec![("Left index".to_string(), self.presses as f32)]
+    }
+  }
+
+  fn left_index() -> Vec<Box<dyn Metric>> {
+    vec![Box::new(LeftIndex::default())]
+  }
+
+  #[test]
+  fn custom_metrics() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let model = model::Model {
+      metrics: Some(left_index),
+      ..Default::default()
+    };
+
+    let tl = analyze::gen_timeline_text("fgrt de", false, lay, &model);
+    // The built in metrics come first
+    let names: Vec<&str> = tl.metrics.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names, vec!["Distance (u)", "Presses", "Modifier presses", "Switches", SAME_FINGER, "Left index"]);
+    assert_eq!(tl.metric("Left index"), 4.0);
+    assert_eq!(tl.metric("Distance (u)"), tl.total_dist);
+    assert_eq!(tl.metric("Presses"), tl.total_presses() as f32);
+    // f g, g r, r t and d e, but not t d across the space
+    assert_eq!(tl.metric(SAME_FINGER), 4.0);
+
+    // Slices of a parallel run add up
+    let tl = analyze::gen_timeline_text("fgrt de fgrt", true, lay, &model);
+    assert_eq!(tl.metric("Left index"), 8.0);
+    assert!(analyze::stats_string(&tl).contains("\nLeft index: 8"));
+
+    // Repeats of one key aren't same finger bigrams
+    let tl = analyze::gen_timeline_text("ded", false, lay, &model);
+    assert_eq!(tl.metric(SAME_FINGER), 2.0);
+    let tl = analyze::gen_timeline_text("dd", false, lay, &model);
+    assert_eq!(tl.metric(SAME_FINGER), 0.0);
+  }
+}
@@ -0,0 +1,553 @@
+use super::analyze;
+use super::effort;
+use super::layout;
+use super::metric;
+
+// Options for how the typist is simulated
+#[derive(Default, Clone)]
+pub struct Model {
+  pub timing: Timing,
+  pub errors: Option<ErrorModel>,
+  pub shift: ShiftModel,
+  pub mobile: Option<MobileModel>,
+  pub effort: Option<effort::EffortConfig>,
+  pub metrics: Option<metric::MetricSet>, // Extra metrics to measure
+}
+
+impl Model {
+  // Copy for one slice of a parallel run, so slices don't all make
+  // the same mistakes
+  pub fn for_slice(&self, i: usize) -> Model {
+    let mut res = self.clone();
+    if let Some(errs) = res.errors.as_mut() {
+      errs.seed = errs.seed.wrapping_add(i as u64);
+    }
+    res
+  }
+}
+
+// How fast the typist is. Fingers move move_speed ms per u, unless
+// finger_speed has a speed for their index
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timing {
+  pub press_dur: i32, // ms a key is held
+  pub press_gap: i32, // ms between the end of a press and the next
+  pub move_speed: f32,
+  pub finger_speed: Vec<f32>,
+  pub same_hand: i32, // ms more a hand waits after its last press before moving
+}
+
+impl Default for Timing {
+  fn default() -> Self {
+    Timing {
+      press_dur: analyze::PRESS_DUR,
+      press_gap: analyze::PRESS_GAP,
+      move_speed: analyze::MOVE_SPEED,
+      finger_speed: Vec::new(),
+      same_hand: 0,
+    }
+  }
+}
+
+impl Timing {
+  pub fn speed(&self, finger: usize) -> f32 {
+    self.finger_speed.get(finger).copied().unwrap_or(self.move_speed)
+  }
+
+  pub fn move_time(&self, finger: usize, start: &layout::Pos, end: &layout::Pos) -> i32 {
+    (analyze::move_dist(start, end) * self.speed(finger)) as i32
+  }
+}
+
+// Timing files are CSV files with a name,value header and a row per
+// value to change from the defaults: press_dur, press_gap, move_speed,
+// same_hand, and finger_N for the speed of finger N. Lines starting
+// with # are comments
+pub fn read_timing(path: &str) -> Option<Timing> {
+  let mut reader = match csv::ReaderBuilder::new().comment(Some(b'#')).trim(csv::Trim::All).from_path(path) {
+    Ok(r) => r,
+    Err(e) => {
+      println!("{}", e);
+      return None;
+    }
+  };
+
+  let mut timing = Timing::default();
+  let mut fingers: Vec<(usize, f32)> = Vec::new();
+  for res in reader.records() {
+    let record = match res {
+      Ok(r) => r,
+      Err(e) => {
+        println!("{}", e);
+        return None;
+      }
+    };
+
+    let name = record.get(0)?;
+    let value = match record.get(1).and_then(|v| v.parse::<f32>().ok()) {
+      Some(v) => v,
+      None => {
+        println!("Timing value {} needs a number", name);
+        return None;
+      }
+    };
+
+    match name {
+      "press_dur" => timing.press_dur = value as i32,
+      "press_gap" => timing.press_gap = value as i32,
+      "move_speed" => timing.move_speed = value,
+      "same_hand" => timing.same_hand = value as i32,
+      _ => match name.strip_prefix("finger_").and_then(|i| i.parse::<usize>().ok()) {
+        Some(i) => fingers.push((i, value)),
+        None => {
+          println!("Unknown timing value {}", name);
+          return None;
+        }
+      },
+    }
+  }
+
+  // Fingers without a speed of their own use move_speed
+  for (i, speed) in fingers {
+    if timing.finger_speed.len() <= i {
+      timing.finger_speed.resize(i + 1, f32::NAN);
+    }
+    timing.finger_speed[i] = speed;
+  }
+  for speed in timing.finger_speed.iter_mut().filter(|s| s.is_nan()) {
+    *speed = timing.move_speed;
+  }
+
+  Some(timing)
+}
+
+pub fn write_timing(timing: &Timing, path: &str) -> csv::Result<()> {
+  let mut writer = csv::Writer::from_path(path)?;
+  writer.write_record(["name", "value"])?;
+  writer.write_record(["press_dur".to_string(), timing.press_dur.to_string()])?;
+  writer.write_record(["press_gap".to_string(), timing.press_gap.to_string()])?;
+  writer.write_record(["move_speed".to_string(), timing.move_speed.to_string()])?;
+  writer.write_record(["same_hand".to_string(), timing.same_hand.to_string()])?;
+  for (i, speed) in timing.finger_speed.iter().enumerate() {
+    writer.write_record([format!("finger_{}", i), speed.to_string()])?;
+  }
+
+  writer.flush()?;
+  Ok(())
+}
+
+// Thumb typing on a touch screen. Keys are key_pitch mm apart, and
+// thumbs stay on the last key they pressed instead of going home.
+// Moves take as long as on a keyboard for the same number of keys,
+// since aiming at smaller keys makes up for the shorter distance
+#[derive(Clone)]
+pub struct MobileModel {
+  pub key_pitch: f32,
+}
+
+// Chance of pressing a neighbouring key instead of the right one, and
+// then fixing it with backspace. The chance of an error on a press is
+// rate * finger weight * (1 + reach_weight * u from home), multiplied
+// by same_finger_weight if the last press used the same finger
+#[derive(Clone)]
+pub struct ErrorModel {
+  pub rate: f32,
+  pub reach_weight: f32,
+  pub same_finger_weight: f32,
+  pub seed: u64,
+}
+
+static PINKY_WEIGHT: f32 = 1.5;
+static RING_WEIGHT: f32 = 1.2;
+static NEIGHBOUR_DIST: f32 = 1.5; // in u, how far away a mistyped key can be
+
+impl ErrorModel {
+  pub fn new(rate: f32, seed: u64) -> Self {
+    ErrorModel {
+      rate,
+      reach_weight: 1.0,
+      same_finger_weight: 2.0,
+      seed,
+    }
+  }
+
+  // How likely pressing key is to go wrong
+  pub fn chance(&self, key: &layout::Key, prev_finger: i16, lay: &layout::Layout) -> f32 {
+    let finger_weight = match lay.fingers.get(key.finger as usize).map(|f| f.kind) {
+      Some(layout::FingerKind::Pinky) => PINKY_WEIGHT,
+      Some(layout::FingerKind::Ring) => RING_WEIGHT,
+      _ => 1.0,
+    };
+
+    let home = lay.homes[key.finger as usize];
+    let reach = analyze::move_dist(&home.pos, &key.pos);
+
+    let mut chance = self.rate * finger_weight * (1.0 + self.reach_weight * reach);
+    if key.finger == prev_finger {
+      chance *= self.same_finger_weight;
+    }
+
+    chance.min(1.0)
+  }
+}
+
+// Small xorshift generator so runs are reproducible from a seed
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    // xorshift gets stuck on 0
+    Rng {
+      state: seed ^ 0x9E37_79B9_7F4A_7C15,
+    }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state << 13;
+    self.state ^= self.state >> 7;
+    self.state ^= self.state << 17;
+    self.state
+  }
+
+  // Uniform in [0, 1)
+  pub fn next_f32(&mut self) -> f32 {
+    ((self.next_u64() >> 40) as f32) / ((1u64 << 24) as f32)
+  }
+}
+
+// Error model state during one simulation
+pub struct ErrorState<'a> {
+  model: &'a ErrorModel,
+  rng: Rng,
+  backspace: &'a layout::Key,
+  prev_finger: i16,
+}
+
+impl<'a> ErrorState<'a> {
+  // None if the layout has no backspace to correct errors with
+  pub fn new(model: &'a ErrorModel, lay: &'a layout::Layout) -> Option<Self> {
+    let backspace = match lay.key_by_name("backspace") {
+      Some(k) => k,
+      None => {
+        println!("Layout has no backspace key, not simulating errors");
+        return None;
+      }
+    };
+
+    Some(ErrorState {
+      model,
+      rng: Rng::new(model.seed),
+      backspace,
+      prev_finger: -1,
+    })
+  }
+
+  // Decide whether typing combo goes wrong. If it does, return the
+  // mistyped combo and the backspace that fixes it
+  pub fn roll<'l>(&mut self, combo: &layout::Combo<'l>, lay: &'l layout::Layout) -> Option<(layout::Combo<'l>, layout::Combo<'l>)>
+  where
+    'a: 'l,
+  {
+    let key = combo.key;
+    if key.finger < 0 {
+      self.prev_finger = key.finger;
+      return None;
+    }
+    let chance = self.model.chance(key, self.prev_finger, lay);
+    self.prev_finger = key.finger;
+
+    if self.rng.next_f32() >= chance {
+      return None;
+    }
+
+    let neighbours: Vec<&layout::Key> = lay
+      .keys
+      .iter()
+      .filter(|k| k.pressed != '\0' && k.finger >= 0 && !std::ptr::eq(*k, key))
+      .filter(|k| analyze::move_dist(&k.pos, &key.pos) <= NEIGHBOUR_DIST)
+      .collect();
+    if neighbours.is_empty() {
+      return None;
+    }
+
+    let wrong = neighbours[(self.rng.next_u64() % (neighbours.len() as u64)) as usize];
+    Some((
+      layout::Combo {
+        key: wrong,
+        mods: combo.mods.clone(),
+      },
+      layout::Combo {
+        key: self.backspace,
+        mods: None,
+      },
+    ))
+  }
+}
+
+// Which shift key to hold for shifted chars
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ShiftPolicy {
+  #[default]
+  Opposite, // The other hand's shift, as the layout picks it
+  Same,     // The shift on the same hand as the key
+  Left,     // Always lshift
+  Right,    // Always rshift
+  Sticky,   // The layout's shift, tapped before the key instead of held
+}
+
+pub fn parse_shift_policy(s: &str) -> Option<ShiftPolicy> {
+  match s.to_ascii_lowercase().as_str() {
+    "opposite" => Some(ShiftPolicy::Opposite),
+    "same" => Some(ShiftPolicy::Same),
+    "left" => Some(ShiftPolicy::Left),
+    "right" => Some(ShiftPolicy::Right),
+    "sticky" | "oneshot" => Some(ShiftPolicy::Sticky),
+    _ => None,
+  }
+}
+
+// How capitals get typed. Runs of at least caps_lock_min capital
+// letters are typed with caps lock on, and shorter ones of at least
+// caps_word_min with Caps Word, which turns itself off after the word
+#[derive(Default, Clone)]
+pub struct ShiftModel {
+  pub policy: ShiftPolicy,
+  pub caps_lock_min: Option<usize>,
+  pub caps_word_min: Option<usize>,
+}
+
+// Caps Word is turned on with a key of this name, or both shifts
+static CAPS_WORD_KEY: &str = "caps_word";
+
+// Shift model state during one simulation
+pub struct ShiftState<'a> {
+  model: &'a ShiftModel,
+  lay: &'a layout::Layout<'a>,
+  fingers: &'a [layout::Finger],
+  lshift: Option<&'a layout::Key>,
+  rshift: Option<&'a layout::Key>,
+  caps_lock: Option<layout::Combo<'a>>,
+  caps_word: Option<layout::Combo<'a>>,
+  caps_left: usize, // Capitals left to type in the current caps run
+  lock_on: bool,
+}
+
+impl<'a> ShiftState<'a> {
+  pub fn new(model: &'a ShiftModel, lay: &'a layout::Layout<'a>) -> Self {
+    let lshift = lay.key_by_name("lshift");
+    let rshift = lay.key_by_name("rshift");
+
+    let caps_lock = lay.key_by_name("caps").map(|key| layout::Combo { key, mods: None });
+    let caps_word = match (lay.key_by_name(CAPS_WORD_KEY), lshift, rshift) {
+      (Some(key), _, _) => Some(layout::Combo { key, mods: None }),
+      (None, Some(l), Some(r)) => Some(layout::Combo {
+        key: l,
+        mods: Some(vec![r]),
+      }),
+      _ => None,
+    };
+
+    ShiftState {
+      model,
+      lay,
+      fingers: &lay.fingers,
+      lshift,
+      rshift,
+      caps_lock,
+      caps_word,
+      caps_left: 0,
+      lock_on: false,
+    }
+  }
+
+  // The combos to press for the stroke at byte idx of text, which the
+  // layout types with combos
+  pub fn combos<'l>(&mut self, text: &str, idx: usize, ch: Option<char>, combos: &[layout::Combo<'l>]) -> Vec<layout::Combo<'l>>
+  where
+    'a: 'l,
+  {
+    let mut res = Vec::new();
+
+    if !is_capital(ch, combos) {
+      // Caps Word turns off at the end of the word, caps lock
+      // has to be pressed
+      if self.lock_on {
+        res.push(self.caps_lock.clone().unwrap());
+        self.lock_on = false;
+      }
+      self.caps_left = 0;
+      for combo in combos {
+        self.apply_policy(combo, &mut res);
+      }
+      return res;
+    }
+
+    if self.caps_left == 0 {
+      let run = text[idx..]
+        .chars()
+        .take_while(|c| is_capital(Some(*c), self.lay.combos_for(*c).unwrap_or(&[])))
+        .count();
+      let at_least = |min: Option<usize>| min.is_some_and(|m| run >= m);
+
+      if at_least(self.model.caps_lock_min) && self.caps_lock.is_some() {
+        res.push(self.caps_lock.clone().unwrap());
+        self.lock_on = true;
+        self.caps_left = run;
+      } else if at_least(self.model.caps_word_min) && self.caps_word.is_some() {
+        res.push(self.caps_word.clone().unwrap());
+        self.lock_on = false;
+        self.caps_left = run;
+      }
+    }
+
+    if self.caps_left == 0 {
+      self.apply_policy(&combos[0], &mut res);
+      return res;
+    }
+
+    res.push(without_shift(&combos[0]));
+    self.caps_left -= 1;
+    if self.caps_left == 0 && self.lock_on {
+      res.push(self.caps_lock.clone().unwrap());
+      self.lock_on = false;
+    }
+
+    res
+  }
+
+  fn apply_policy<'l>(&self, combo: &layout::Combo<'l>, res: &mut Vec<layout::Combo<'l>>)
+  where
+    'a: 'l,
+  {
+    let shift = match shift_of(combo) {
+      Some(s) => s,
+      None => {
+        res.push(combo.clone());
+        return;
+      }
+    };
+
+    let same_hand = match self.fingers.get(combo.key.finger as usize).map(|f| f.hand) {
+      Some(layout::Hand::Left) => self.lshift,
+      Some(layout::Hand::Right) => self.rshift,
+      None => None,
+    };
+    let replacement = match self.model.policy {
+      ShiftPolicy::Opposite => None,
+      ShiftPolicy::Same => same_hand,
+      ShiftPolicy::Left => self.lshift,
+      ShiftPolicy::Right => self.rshift,
+      ShiftPolicy::Sticky => {
+        res.push(layout::Combo { key: shift, mods: None });
+        res.push(without_shift(combo));
+        return;
+      }
+    };
+
+    let mut combo = combo.clone();
+    if let (Some(new), Some(mods)) = (replacement, combo.mods.as_mut()) {
+      for m in mods.iter_mut().filter(|m| is_shift(m)) {
+        *m = new;
+      }
+    }
+    res.push(combo);
+  }
+}
+
+// Capitals typed with a single shifted combo, which caps lock and
+// Caps Word can type instead
+fn is_capital(ch: Option<char>, combos: &[layout::Combo]) -> bool {
+  ch.is_some_and(|c| c.is_uppercase()) && combos.len() == 1 && shift_of(&combos[0]).is_some()
+}
+
+fn is_shift(key: &layout::Key) -> bool {
+  key.visual.name.ends_with("shift")
+}
+
+fn shift_of<'l>(combo: &layout::Combo<'l>) -> Option<&'l layout::Key> {
+  combo.mods.as_ref()?.iter().copied().find(|m| is_shift(m))
+}
+
+fn without_shift<'l>(combo: &layout::Combo<'l>) -> layout::Combo<'l> {
+  let mods: Vec<&layout::Key> = combo.mods.iter().flatten().copied().filter(|m| !is_shift(m)).collect();
+  layout::Combo {
+    key: combo.key,
+    mods: if mods.is_empty() { None } else { Some(mods) },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rng_repeatable() {
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    let mut c = Rng::new(8);
+
+    let a_vals: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+    let b_vals: Vec<u64> = (0..10).map(|_| b.next_u64()).collect();
+    let c_vals: Vec<u64> = (0..10).map(|_| c.next_u64()).collect();
+    assert_eq!(a_vals, b_vals);
+    assert_ne!(a_vals, c_vals);
+
+    for _ in 0..1000 {
+      let f = a.next_f32();
+      assert!((0.0..1.0).contains(&f));
+    }
+  }
+
+  #[test]
+  fn timing_file() {
+    let timing = read_timing("test/slow.timing").unwrap();
+    assert_eq!(timing.press_dur, 80);
+    assert_eq!(timing.press_gap, analyze::PRESS_GAP);
+    assert_eq!(timing.finger_speed, vec![200.0, 200.0, 200.0, 250.0]);
+    assert_eq!(timing.speed(3), 250.0);
+    assert_eq!(timing.speed(7), 200.0);
+
+    let path = std::env::temp_dir().join("layout-speed-test.timing");
+    let path = path.to_str().unwrap();
+    write_timing(&timing, path).unwrap();
+    assert_eq!(read_timing(path), Some(timing));
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn chance() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let model = ErrorModel::new(0.01, 0);
+
+    let f = lay.char_keys.get(&'f').unwrap().key;
+    let t = lay.char_keys.get(&'t').unwrap().key;
+    let a = lay.char_keys.get(&'a').unwrap().key;
+
+    // Home keys only get the base rate
+    assert_eq!(model.chance(f, -1, lay), 0.01);
+    // Reaching and pinkies make it worse
+    assert!(model.chance(t, -1, lay) > model.chance(f, -1, lay));
+    assert!(model.chance(a, -1, lay) > model.chance(f, -1, lay));
+    // As does using the same finger twice
+    assert_eq!(model.chance(f, 3, lay), 0.02);
+
+    // Keys without a finger are never mistyped
+    let mut errors = ErrorState::new(&model, lay).unwrap();
+    let unfingered = layout::Combo {
+      key: &layout::DUMMY_KEY,
+      mods: None,
+    };
+    assert!(errors.roll(&unfingered, lay).is_none());
+
+    // Nor are they typed by mistake
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "test/unfingered.layout").unwrap();
+    let model = ErrorModel::new(1.0, 0);
+    let mut errors = ErrorState::new(&model, lay).unwrap();
+    let a = lay.char_keys.get(&'a').unwrap().clone();
+    assert!(errors.roll(&a, lay).is_none());
+  }
+}
@@ -0,0 +1,220 @@
+use super::analyze;
+use super::input;
+use super::layout;
+use super::model;
+
+// A shortcut or editor command written with the same notation as
+// text input, e.g. "<C-S-p>" or "ciw", and how often it's used
+pub struct Shortcut {
+  pub keys: String,
+  pub frequency: u32,
+}
+
+pub struct ShortcutCost {
+  pub shortcut: Shortcut,
+  pub typeable: bool,
+  pub fingers: Vec<i16>, // In order of first use
+  pub same_hand: u32,    // # of chords with more than one key on a hand
+  pub time: i32,
+  pub dist: f32, // in u
+}
+
+impl ShortcutCost {
+  pub fn weighted_time(&self) -> u64 {
+    (self.time as u64) * (self.shortcut.frequency as u64)
+  }
+}
+
+// Workloads are CSV files with a header and a row per shortcut:
+// shortcut,frequency
+pub fn read_workload(path: &str) -> Option<Vec<Shortcut>> {
+  let mut reader = match csv::ReaderBuilder::new().from_path(path) {
+    Ok(r) => r,
+    Err(e) => {
+      println!("{}", e);
+      return None;
+    }
+  };
+
+  let mut shortcuts = Vec::new();
+  for res in reader.records() {
+    let record = match res {
+      Ok(r) => r,
+      Err(e) => {
+        println!("{}", e);
+        return None;
+      }
+    };
+
+    let keys = match record.get(0) {
+      Some(k) if !k.is_empty() => k.to_string(),
+      _ => continue,
+    };
+    let frequency = record.get(1).and_then(|f| f.trim().parse().ok()).unwrap_or(1);
+
+    shortcuts.push(Shortcut { keys, frequency });
+  }
+
+  Some(shortcuts)
+}
+
+pub fn cost(shortcut: Shortcut, lay: &layout::Layout, model: &model::Model) -> ShortcutCost {
+  let mut typeable = true;
+  let mut fingers = Vec::new();
+  let mut same_hand = 0;
+
+  for stroke in input::strokes(&shortcut.keys, lay) {
+    let combos = match stroke.combos {
+      Some(cs) => cs,
+      None => {
+        typeable = false;
+        continue;
+      }
+    };
+
+    for combo in combos.iter() {
+      let mut chord = vec![combo.key.finger];
+      if let Some(mods) = &combo.mods {
+        chord.extend(mods.iter().map(|m| m.finger));
+      }
+
+      let on_hand = |hand| {
+        let uses = |f: &&i16| **f >= 0 && analyze::uses_hand(lay, **f as usize, hand);
+        chord.iter().filter(uses).count()
+      };
+      let left = on_hand(layout::Hand::Left);
+      let right = on_hand(layout::Hand::Right);
+      if left > 1 || right > 1 {
+        same_hand += 1;
+      }
+
+      for finger in chord {
+        if !fingers.contains(&finger) {
+          fingers.push(finger);
+        }
+      }
+    }
+  }
+
+  let tl = analyze::gen_timeline_model(&shortcut.keys, false, lay, model);
+
+  ShortcutCost {
+    shortcut,
+    typeable,
+    fingers,
+    same_hand,
+    time: tl.total_time,
+    dist: tl.total_dist,
+  }
+}
+
+// Costs of every shortcut, most expensive overall first
+pub fn analyze_workload(workload: Vec<Shortcut>, lay: &layout::Layout, model: &model::Model) -> Vec<ShortcutCost> {
+  let mut costs: Vec<ShortcutCost> = workload.into_iter().map(|s| cost(s, lay, model)).collect();
+  costs.sort_by(|a, b| b.weighted_time().cmp(&a.weighted_time()).then(a.shortcut.keys.cmp(&b.shortcut.keys)));
+  costs
+}
+
+pub fn print_report(costs: &[ShortcutCost]) {
+  let total_weighted: u64 = costs.iter().map(|c| c.weighted_time()).sum();
+  let total_freq: u64 = costs.iter().map(|c| c.shortcut.frequency as u64).sum();
+
+  println!(
+    "{:<16} {:>6} {:>7} {:>7} {:>10} {:>7}  Fingers",
+    "Shortcut", "Freq", "Time", "Dist", "Same-hand", "Share"
+  );
+  for c in costs {
+    let fingers: Vec<String> = c.fingers.iter().map(|f| f.to_string()).collect();
+    let share = if total_weighted == 0 {
+      0.0
+    } else {
+      (c.weighted_time() as f32) * 100.0 / (total_weighted as f32)
+    };
+
+    println!(
+      "{:<16} {:>6} {:>5}ms {:>6.2}u {:>10} {:>6.1}%  {}{}",
+      c.shortcut.keys,
+      c.shortcut.frequency,
+      c.time,
+      c.dist,
+      c.same_hand,
+      share,
+      fingers.join(","),
+      if c.typeable { "" } else { " (can't be fully typed)" }
+    );
+  }
+
+  if let Some(avg) = total_weighted.checked_div(total_freq) {
+    println!("Average time per shortcut: {}ms", avg);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn shortcut(keys: &str) -> Shortcut {
+    Shortcut {
+      keys: keys.to_string(),
+      frequency: 1,
+    }
+  }
+
+  #[test]
+  fn chords() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let model = model::Model::default();
+
+    // Opposite hand ctrl
+    let copy = cost(shortcut("<C-c>"), lay, &model);
+    assert!(copy.typeable);
+    assert_eq!(copy.fingers, vec![2, 9]);
+    assert_eq!(copy.same_hand, 0);
+
+    // Ctrl and shift both on the left pinky
+    let palette = cost(shortcut("<C-S-p>"), lay, &model);
+    assert_eq!(palette.fingers, vec![9, 0]);
+    assert_eq!(palette.same_hand, 1);
+    assert!(palette.time > 0);
+
+    let delete_line = cost(shortcut("dd"), lay, &model);
+    assert_eq!(delete_line.fingers, vec![2]);
+    assert_eq!(delete_line.dist, 0.0);
+  }
+
+  #[test]
+  fn sorted_by_weight() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let model = model::Model::default();
+
+    let workload = vec![
+      shortcut("<C-S-p>"),
+      Shortcut {
+        keys: "ciw".to_string(),
+        frequency: 50,
+      },
+      shortcut("<C-é>"),
+    ];
+    let costs = analyze_workload(workload, lay, &model);
+
+    assert_eq!(costs[0].shortcut.keys, "ciw");
+    assert!(!costs.iter().find(|c| c.shortcut.keys == "<C-é>").unwrap().typeable);
+  }
+
+  #[test]
+  fn model() {
+    let mut lay = layout::Layout::default();
+    let lay = layout::init(&mut lay, "layouts/qwerty.layout").unwrap();
+    let fast = cost(shortcut("ciw"), lay, &model::Model::default());
+
+    let slow = model::Model {
+      timing: model::read_timing("test/slow.timing").unwrap(),
+      ..Default::default()
+    };
+    assert!(cost(shortcut("ciw"), lay, &slow).time > fast.time);
+
+    assert!(read_workload("test/missing.workload").is_none());
+  }
+}